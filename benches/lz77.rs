@@ -0,0 +1,102 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mobi::lz77;
+
+const RECORD_SIZE: usize = 4096;
+
+/// Builds a text record compressed with a mix of literals, long non-overlapping
+/// back references and short overlapping ones, as commonly found in books.
+fn compressed_record() -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut len = 0;
+    while len < RECORD_SIZE - 32 {
+        data.extend_from_slice(b"\x08<p>lorem");
+        data.extend_from_slice(b" ipsum dolor sit amet");
+        len += 8 + 21;
+        // distance 29, length 10
+        data.extend_from_slice(&(0x8000u16 | 29 << 3 | 7).to_be_bytes());
+        // distance 2, length 8
+        data.extend_from_slice(&(0x8000u16 | 2 << 3 | 5).to_be_bytes());
+        data.extend_from_slice(b"\xe1\xee");
+        len += 10 + 8 + 4;
+    }
+    data
+}
+
+/// Previous implementation, pushing output byte by byte
+fn byte_by_byte(data: &[u8]) -> Vec<u8> {
+    let length = data.len();
+    let mut offset: usize = 0;
+    let mut text: Vec<u8> = vec![];
+    while offset < length {
+        let byte = data[offset];
+        offset += 1;
+        match byte {
+            0x0 | 0x09..=0x7f => text.push(byte),
+            0x1..=0x8 => {
+                if offset + byte as usize <= length {
+                    data[offset..(offset + byte as usize)].iter().for_each(|ch| {
+                        text.push(*ch);
+                    });
+                    offset += byte as usize;
+                }
+            }
+            0x80..=0xbf => {
+                offset += 1;
+                if offset > length {
+                    return text;
+                }
+                let lz77 = u16::from_be_bytes([byte, data[offset - 1]]) & 0x3fff;
+                let lz77length = (lz77 & 0x0007) + 3;
+                let lz77offset = lz77 >> 3;
+                if lz77offset < 1 {
+                    return text;
+                }
+                for _ in 0..lz77length {
+                    let mut textpos: usize = text.len();
+                    if textpos >= lz77offset as usize {
+                        textpos -= lz77offset as usize;
+                    } else {
+                        break;
+                    }
+                    let ch = &text[textpos..=textpos].to_owned();
+                    for c in ch {
+                        text.push(*c);
+                    }
+                }
+            }
+            _ => {
+                text.push(b' ');
+                text.push(byte ^ 0x80);
+            }
+        }
+    }
+    text
+}
+
+fn bench_lz77(c: &mut Criterion) {
+    let data = compressed_record();
+    let mut expected = Vec::new();
+    lz77::decompress(&data, &mut expected).unwrap();
+    assert_eq!(byte_by_byte(&data), expected);
+
+    let mut group = c.benchmark_group("lz77");
+    group.bench_function("byte_by_byte", |b| b.iter(|| byte_by_byte(black_box(&data))));
+    group.bench_function("decompress", |b| {
+        b.iter(|| {
+            let mut out = Vec::with_capacity(RECORD_SIZE);
+            lz77::decompress(black_box(&data), &mut out).unwrap();
+            out
+        })
+    });
+    group.bench_function("decompress_reused_buffer", |b| {
+        let mut out = Vec::with_capacity(RECORD_SIZE);
+        b.iter(|| {
+            out.clear();
+            lz77::decompress(black_box(&data), &mut out).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_lz77);
+criterion_main!(benches);
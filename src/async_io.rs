@@ -0,0 +1,505 @@
+use crate::headers::records::Records;
+use crate::headers::{Header, MobiMetadata, TextEncoding, PDB_HEADER_LEN};
+use crate::reader::{MobiReader, Reader};
+use crate::record::{self, Record, TextRecords};
+use crate::{text, Diagnostics, Mobi, ParseOptions, RecordKind, Source};
+use std::borrow::Cow;
+use std::error::Error;
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+/// Future of bytes read by `Fetch::fetch`
+type FetchFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<u8>>> + Send + 'a>>;
+
+/// Source of content read at increasing offsets
+trait Fetch {
+    /// Reads up to `len` bytes located at `offset`, returning less of them only
+    /// if the content ends earlier.
+    fn fetch(&mut self, offset: u64, len: u64) -> FetchFuture<'_>;
+}
+
+/// Reader that can only be read forward, skipping content between fetched parts
+struct Stream<R> {
+    reader: R,
+    position: u64,
+}
+
+impl<R: AsyncRead + Unpin + Send> Fetch for Stream<R> {
+    fn fetch(&mut self, offset: u64, len: u64) -> FetchFuture<'_> {
+        Box::pin(async move {
+            if offset < self.position {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "records are not stored in order of their offsets",
+                ));
+            }
+            self.position += tokio::io::copy(
+                &mut (&mut self.reader).take(offset - self.position),
+                &mut tokio::io::sink(),
+            )
+            .await?;
+
+            let mut buf = Vec::new();
+            if self.position == offset {
+                (&mut self.reader).take(len).read_to_end(&mut buf).await?;
+                self.position += buf.len() as u64;
+            }
+            Ok(buf)
+        })
+    }
+}
+
+/// Reader that can seek directly to fetched parts
+struct Seekable<'r, R>(&'r mut R);
+
+impl<R: AsyncRead + AsyncSeek + Unpin + Send> Fetch for Seekable<'_, R> {
+    fn fetch(&mut self, offset: u64, len: u64) -> FetchFuture<'_> {
+        Box::pin(async move {
+            self.0.seek(SeekFrom::Start(offset)).await?;
+            let mut buf = Vec::new();
+            (&mut self.0).take(len).read_to_end(&mut buf).await?;
+            Ok(buf)
+        })
+    }
+}
+
+/// Parts of content loaded beforehand at known offsets
+#[derive(Default)]
+struct Segments(Vec<(u64, Vec<u8>)>);
+
+impl Segments {
+    /// Loads the record at `index`
+    async fn fetch_record(&mut self, source: &mut impl Fetch, records: &Records, index: usize) -> io::Result<()> {
+        if let Some((start, end)) = records.bounds(index, u64::MAX) {
+            self.0.push((start, source.fetch(start, end - start).await?));
+        }
+        Ok(())
+    }
+}
+
+impl Source for Segments {
+    /// Reads bytes from the segment containing `offset`, which are cut short at
+    /// the end of the segment.
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        Ok(self
+            .0
+            .iter()
+            .find(|(start, data)| *start <= offset && offset < start + data.len() as u64)
+            .map(|(start, data)| {
+                let from = (offset - start) as usize;
+                data[from..from + len.min(data.len() - from)].to_vec()
+            })
+            .unwrap_or_default())
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self
+            .0
+            .iter()
+            .map(|(start, data)| start + data.len() as u64)
+            .max()
+            .unwrap_or(0))
+    }
+}
+
+/// Loads parts of content holding headers and parses them
+async fn load_metadata(source: &mut impl Fetch, options: &ParseOptions) -> io::Result<MobiMetadata> {
+    let header_len = PDB_HEADER_LEN as u64;
+    let mut segments = Segments::default();
+    segments.0.push((0, source.fetch(0, header_len).await?));
+    let header = Header::parse(&mut Reader::new(&mut segments))?;
+    let list_len = 8 * u64::from(header.num_records) + 2;
+    segments.0.push((header_len, source.fetch(header_len, list_len).await?));
+    let records = {
+        let mut reader = Reader::new(&mut segments);
+        reader.set_position(header_len);
+        reader.set_num_records(header.num_records);
+        Records::parse(&mut reader)?
+    };
+
+    segments.fetch_record(source, &records, 0).await?;
+    // Headers of the KF8 part are known to be needed only once EXTH is parsed, so
+    // the first pass is lenient not to fail on KF8 records which aren't fetched yet
+    let metadata = MobiMetadata::from_source_with_options(&mut segments, &options.clone().lenient())?;
+
+    let mut fetched = false;
+    if let (None, Some(kf8_record0)) = (&metadata.kf8, metadata.exth.kf8_boundary()) {
+        if kf8_record0 > 0 && (kf8_record0 as usize) < records.records.len() {
+            segments
+                .fetch_record(source, &records, kf8_record0 as usize - 1)
+                .await?;
+            segments.fetch_record(source, &records, kf8_record0 as usize).await?;
+            fetched = true;
+        }
+    }
+    if fetched || options.is_strict() {
+        MobiMetadata::from_source_with_options(&mut segments, options)
+    } else {
+        Ok(metadata)
+    }
+}
+
+impl Mobi<'static> {
+    /// Construct a Mobi object from an object that implements tokio's AsyncRead trait.
+    /// Only available with feature `tokio`.
+    pub async fn from_async_read<R: AsyncRead + Unpin + Send>(reader: R) -> io::Result<Mobi<'static>> {
+        Mobi::from_async_read_with_options(reader, &ParseOptions::default()).await
+    }
+
+    /// Construct a Mobi object from an object that implements tokio's AsyncRead trait,
+    /// parsed with passed options. When content is skipped only headers are read.
+    /// Only available with feature `tokio`.
+    pub async fn from_async_read_with_options<R: AsyncRead + Unpin + Send>(
+        reader: R,
+        options: &ParseOptions,
+    ) -> io::Result<Mobi<'static>> {
+        if options.skip_content {
+            let metadata = MobiMetadata::from_async_read_with_options(reader, options).await?;
+            return Ok(Mobi::without_content(metadata, options));
+        }
+        let mut content = Vec::new();
+        // Reading a byte past the limit is enough to tell it's exceeded
+        reader
+            .take(options.max_file_size.saturating_add(1))
+            .read_to_end(&mut content)
+            .await?;
+        Mobi::from_content(Cow::Owned(content), options)
+    }
+}
+
+impl MobiMetadata {
+    /// Construct a Metadata object from an object that implements tokio's AsyncRead
+    /// trait. Just like with `from_read` the reader is consumed only up to the end
+    /// of headers. Only available with feature `tokio`.
+    pub async fn from_async_read<R: AsyncRead + Unpin + Send>(reader: R) -> io::Result<MobiMetadata> {
+        MobiMetadata::from_async_read_with_options(reader, &ParseOptions::default()).await
+    }
+
+    /// Construct a Metadata object from an object that implements tokio's AsyncRead
+    /// trait, parsed with passed options. Only available with feature `tokio`.
+    pub async fn from_async_read_with_options<R: AsyncRead + Unpin + Send>(
+        reader: R,
+        options: &ParseOptions,
+    ) -> io::Result<MobiMetadata> {
+        load_metadata(&mut Stream { reader, position: 0 }, options).await
+    }
+}
+
+#[derive(Debug)]
+/// Asynchronous counterpart of `LazyMobi`, reading and decompressing records from
+/// an object implementing tokio's AsyncRead and AsyncSeek traits only when they
+/// are requested. Only available with feature `tokio`.
+pub struct AsyncLazyMobi<R> {
+    reader: R,
+    len: u64,
+    pub metadata: MobiMetadata,
+    options: ParseOptions,
+    /// Kinds of records, found out on first use
+    kinds: Option<Vec<RecordKind>>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncLazyMobi<R> {
+    /// Construct an AsyncLazyMobi object from an object that implements AsyncRead
+    /// and AsyncSeek traits. Only headers are read at this point.
+    pub async fn new(reader: R) -> io::Result<AsyncLazyMobi<R>>
+    where
+        R: Send,
+    {
+        AsyncLazyMobi::new_with_options(reader, &ParseOptions::default()).await
+    }
+
+    /// Construct an AsyncLazyMobi object from an object that implements AsyncRead
+    /// and AsyncSeek traits, parsed with passed options. Skipping content has no
+    /// effect, as it is read only when requested.
+    pub async fn new_with_options(mut reader: R, options: &ParseOptions) -> io::Result<AsyncLazyMobi<R>>
+    where
+        R: Send,
+    {
+        let metadata = load_metadata(&mut Seekable(&mut reader), options).await?;
+        let len = reader.seek(SeekFrom::End(0)).await?;
+        options.check_file_size(len)?;
+        Ok(AsyncLazyMobi {
+            reader,
+            len,
+            metadata,
+            options: options.clone(),
+            kinds: None,
+        })
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Returns number of PDB records in the book
+    pub fn record_count(&self) -> usize {
+        self.metadata.records.records.len()
+    }
+
+    async fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = vec![0; len];
+        self.reader.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Reads raw content of the PDB record located at `index`
+    pub async fn raw_record(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let (start, end) = self
+            .metadata
+            .records
+            .bounds(index, self.len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("record {} does not exist", index)))?;
+        self.read_at(start, (end - start) as usize).await
+    }
+
+    /// Loads the record at `index`, cut short at the end of the file
+    async fn load_record(&mut self, index: usize) -> io::Result<Segments> {
+        let mut segments = Segments::default();
+        if let Some((start, end)) = self.metadata.records.bounds(index, self.len) {
+            segments
+                .0
+                .push((start, self.read_at(start, (end - start) as usize).await?));
+        }
+        Ok(segments)
+    }
+
+    /// Reads and decompresses the text record located at `index`. Returns an
+    /// error if the record doesn't exist or doesn't hold text.
+    pub async fn text_record(&mut self, index: usize) -> io::Result<Record> {
+        if self.kinds().await?.get(index) != Some(&RecordKind::Text) {
+            return Err(record::not_text_record(index));
+        }
+        let mut segments = self.load_record(index).await?;
+        TextRecords::new(&self.metadata, &self.options, self.len).read(&mut segments, index, &mut Diagnostics::new())
+    }
+
+    /// Returns the kind of content held by each record of the book. Only leading
+    /// bytes of records are read to find it out, the first time kinds are needed.
+    pub async fn record_kinds(&mut self) -> io::Result<Vec<RecordKind>> {
+        Ok(self.kinds().await?.to_vec())
+    }
+
+    async fn kinds(&mut self) -> io::Result<&[RecordKind]> {
+        if self.kinds.is_none() {
+            let mut magic = Segments::default();
+            for i in 0..self.record_count() {
+                if let Some((offset, len)) = RecordKind::magic_range(&self.metadata, i, self.len) {
+                    magic.0.push((offset, self.read_at(offset, len).await?));
+                }
+            }
+            self.kinds = Some(RecordKind::read(&self.metadata, &mut magic, self.len)?);
+        }
+        Ok(self.kinds.as_deref().unwrap_or_default())
+    }
+
+    /// Reads every text record of the book, one after another. Records which
+    /// can't be decompressed end up empty, unless parsing is strict.
+    async fn records(&mut self, diagnostics: &mut Diagnostics) -> io::Result<Vec<Record>> {
+        self.kinds().await?;
+        let indexes = self
+            .metadata
+            .text_record_indexes(self.kinds.as_deref().unwrap_or_default());
+        let mut records = Vec::with_capacity(indexes.len());
+        let mut total = 0;
+        for i in indexes {
+            let mut segments = self.load_record(i).await?;
+            let text = TextRecords::new(&self.metadata, &self.options, self.len);
+            let record = text.recover(&mut segments, i, diagnostics)?;
+            total += record.record_data.len() as u64;
+            self.options.check_decompressed_size(total)?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Returns anomalies found while parsing the book leniently, both in headers
+    /// and in text records, which are read and decompressed to find them.
+    pub async fn diagnostics(&mut self) -> io::Result<Diagnostics> {
+        let mut diagnostics = self.metadata.diagnostics.clone();
+        self.records(&mut diagnostics).await?;
+        Ok(diagnostics)
+    }
+
+    /// Returns all readable records content decompressed as a String.
+    /// There are only two supported encodings in mobi format (UTF8, WIN1252)
+    /// and both are losely converted by this function
+    pub async fn content_as_string_lossy(&mut self) -> io::Result<String> {
+        let records = self.records(&mut Diagnostics::new()).await?;
+        Ok(text::decode_records_lossy(&records, self.text_encoding()))
+    }
+
+    /// Returns all readable records content decompressed as a String.
+    /// This function is a strict version returning error on first encountered
+    /// decoding error, unless replacing them was chosen with `ParseOptions::decode_errors`.
+    pub async fn content_as_string(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let records = self.records(&mut Diagnostics::new()).await?;
+        Ok(text::decode_records_with(
+            &records,
+            self.text_encoding(),
+            self.options.decode_errors,
+        )?)
+    }
+
+    /// Returns all readable records content decompressed as a Vec
+    pub async fn content(&mut self) -> io::Result<Vec<u8>> {
+        Ok(text::join_records(&self.records(&mut Diagnostics::new()).await?))
+    }
+
+    /// Returns text encoding used in ebook
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.metadata.text_encoding()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+    use crate::headers::{ExthRecord, NULL_INDEX};
+    use std::io::Cursor;
+
+    fn kf8_book() -> Vec<u8> {
+        let mobi6 = book::Record0 {
+            record_count: 1,
+            compression: 2,
+            exth: vec![(121, 4u32.to_be_bytes().to_vec())],
+            name: "Old",
+            ..Default::default()
+        };
+        let kf8 = book::Record0 {
+            header_length: 264,
+            format_version: 8,
+            exth: vec![(100, b"Author".to_vec())],
+            name: "New",
+            ..Default::default()
+        };
+        let records = [
+            mobi6.build(),
+            b"\x03abc".to_vec(),
+            b"GIF89a".to_vec(),
+            b"BOUNDARY".to_vec(),
+            kf8.build(),
+        ];
+        book::pdb(&records, 2)
+    }
+
+    fn assert_send<T: Send>(t: T) -> T {
+        t
+    }
+
+    #[tokio::test]
+    async fn parses_from_async_read() {
+        let book = kf8_book();
+        let metadata = assert_send(MobiMetadata::from_async_read(&book[..])).await.unwrap();
+        assert_eq!(metadata.mobi.name, "Old");
+        let kf8 = metadata.kf8.unwrap();
+        assert_eq!(kf8.record0, 4);
+        assert_eq!(
+            kf8.exth.get_record_string_lossy(ExthRecord::Author),
+            Some(String::from("Author"))
+        );
+
+        // Truncated stream still yields headers of the MOBI part
+        let metadata = MobiMetadata::from_async_read(&book[..book.len() - 10]).await.unwrap();
+        assert!(metadata.kf8.is_none());
+
+        let m = assert_send(Mobi::from_async_read(&book[..])).await.unwrap();
+        assert_eq!(m.content().unwrap(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn parses_joint_book_strictly() {
+        let book = kf8_book();
+        let options = ParseOptions::new().strict();
+        let metadata = MobiMetadata::from_async_read_with_options(&book[..], &options)
+            .await
+            .unwrap();
+        assert_eq!(metadata.kf8.map(|kf8| kf8.record0), Some(4));
+        let m = AsyncLazyMobi::new_with_options(Cursor::new(book.clone()), &options)
+            .await
+            .unwrap();
+        assert_eq!(m.metadata.kf8.as_ref().map(|kf8| kf8.record0), Some(4));
+
+        // Truncated KF8 headers are an error in strict mode
+        let truncated = &book[..book.len() - 10];
+        assert!(MobiMetadata::from_async_read_with_options(truncated, &options)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn ignores_invalid_kf8_boundary() {
+        let record0 = book::Record0 {
+            exth: vec![(121, NULL_INDEX.to_be_bytes().to_vec())],
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), b"text".to_vec()], 2);
+        assert!(MobiMetadata::from_async_read(&book[..]).await.unwrap().kf8.is_none());
+        assert!(AsyncLazyMobi::new(Cursor::new(book))
+            .await
+            .unwrap()
+            .metadata
+            .kf8
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn reads_records_on_demand() {
+        let mut m = assert_send(AsyncLazyMobi::new(Cursor::new(kf8_book()))).await.unwrap();
+        assert_eq!(m.metadata.kf8.as_ref().map(|kf8| kf8.record0), Some(4));
+        assert_eq!(m.record_count(), 5);
+        assert_eq!(m.raw_record(2).await.unwrap(), b"GIF89a");
+        assert_eq!(m.text_record(1).await.unwrap().record_data, b"abc");
+        assert!(m.text_record(2).await.is_err());
+        assert_eq!(m.record_kinds().await.unwrap()[3], RecordKind::Boundary);
+        assert_eq!(m.content_as_string().await.unwrap(), "abc");
+    }
+
+    #[tokio::test]
+    async fn recovers_like_mobi() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            compression: 2,
+            ..Default::default()
+        };
+        // Back reference of the second record points before the start of its text
+        let records = [record0.build(), b"\x03abc".to_vec(), b"d\x80\x20".to_vec()];
+        let book = book::pdb(&records, 2);
+        let m = Mobi::new(&book).unwrap();
+        let mut lazy = AsyncLazyMobi::new(Cursor::new(book.clone())).await.unwrap();
+
+        assert_eq!(lazy.content().await.unwrap(), m.content().unwrap());
+        assert_eq!(lazy.diagnostics().await.unwrap(), m.diagnostics().unwrap());
+
+        let options = ParseOptions::new().strict();
+        let mut lazy = AsyncLazyMobi::new_with_options(Cursor::new(book), &options)
+            .await
+            .unwrap();
+        assert!(lazy.content().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn parses_with_options() {
+        let book = kf8_book();
+        let options = ParseOptions::new().max_file_size(100);
+        assert!(Mobi::from_async_read_with_options(&book[..], &options).await.is_err());
+        assert!(AsyncLazyMobi::new_with_options(Cursor::new(book.clone()), &options)
+            .await
+            .is_err());
+
+        let options = ParseOptions::new().skip_content(true);
+        let m = Mobi::from_async_read_with_options(&book[..], &options).await.unwrap();
+        assert_eq!(m.metadata.mobi.name, "Old");
+        assert!(m.content().unwrap().is_empty());
+
+        let options = ParseOptions::new().skip_exth(true);
+        let metadata = MobiMetadata::from_async_read_with_options(&book[..], &options)
+            .await
+            .unwrap();
+        assert!(metadata.exth.records.is_empty());
+    }
+}
@@ -143,12 +143,126 @@ pub const BOOK: &[u8] = &[
     32, 82, 105, 110, 103,
 ];
 
+/// Offset of the MOBI header within `full_book`
+pub const MOBIHEADER_OFFSET: usize = HEADER.len() + RECORDS.len() + PALMDOCHEADER.len();
+
 pub(crate) fn full_book() -> Vec<u8> {
     let mut res = Vec::new();
     res.extend_from_slice(&HEADER);
     res.extend_from_slice(&RECORDS);
     res.extend_from_slice(&PALMDOCHEADER);
     res.extend_from_slice(&MOBIHEADER);
-    res.extend_from_slice(&BOOK);
+    res.extend_from_slice(BOOK);
     res
 }
+
+/// Test helper assembling a PDB file out of raw records, with `gap` bytes of
+/// padding between the record list and the first record.
+pub(crate) fn pdb(records: &[Vec<u8>], gap: usize) -> Vec<u8> {
+    let mut res = HEADER.to_vec();
+    res[76..78].copy_from_slice(&(records.len() as u16).to_be_bytes());
+
+    let mut offset = HEADER.len() + records.len() * 8 + gap;
+    for (id, record) in records.iter().enumerate() {
+        res.extend_from_slice(&(offset as u32).to_be_bytes());
+        res.extend_from_slice(&(id as u32 * 2).to_be_bytes());
+        offset += record.len();
+    }
+    res.resize(res.len() + gap, 0);
+    for record in records {
+        res.extend_from_slice(record);
+    }
+    res
+}
+
+/// Test helper describing the most commonly used fields of record 0
+pub(crate) struct Record0 {
+    pub compression: u16,
+    pub text_length: u32,
+    pub record_count: u16,
+    pub record_size: u16,
+    pub header_length: u32,
+    pub text_encoding: u32,
+    pub format_version: u32,
+    pub first_non_book_index: u32,
+    pub first_image_index: u32,
+    pub extra_data_flags: u16,
+    /// Other fields of the MOBI header, by their offset from the start of the record
+    pub fields: Vec<(usize, u32)>,
+    pub exth: Vec<(u32, Vec<u8>)>,
+    pub name: &'static str,
+}
+
+impl Default for Record0 {
+    fn default() -> Record0 {
+        Record0 {
+            compression: 1,
+            text_length: 0,
+            record_count: 0,
+            record_size: 4096,
+            header_length: 232,
+            text_encoding: 65001,
+            format_version: 6,
+            first_non_book_index: 0xFFFF_FFFF,
+            first_image_index: 0xFFFF_FFFF,
+            extra_data_flags: 0,
+            fields: vec![],
+            exth: vec![],
+            name: "Test Book",
+        }
+    }
+}
+
+impl Record0 {
+    pub(crate) fn build(&self) -> Vec<u8> {
+        fn put(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        let mut res = vec![0; 16 + self.header_length as usize];
+        put(&mut res, 0, &self.compression.to_be_bytes());
+        put(&mut res, 4, &self.text_length.to_be_bytes());
+        put(&mut res, 8, &self.record_count.to_be_bytes());
+        put(&mut res, 10, &self.record_size.to_be_bytes());
+        put(&mut res, 16, b"MOBI");
+        put(&mut res, 20, &self.header_length.to_be_bytes());
+        put(&mut res, 24, &2u32.to_be_bytes());
+        put(&mut res, 28, &self.text_encoding.to_be_bytes());
+        put(&mut res, 36, &self.format_version.to_be_bytes());
+        for offset in (40..80).step_by(4) {
+            put(&mut res, offset, &0xFFFF_FFFFu32.to_be_bytes());
+        }
+        put(&mut res, 80, &self.first_non_book_index.to_be_bytes());
+        put(&mut res, 92, &9u32.to_be_bytes());
+        put(&mut res, 104, &self.format_version.to_be_bytes());
+        put(&mut res, 108, &self.first_image_index.to_be_bytes());
+        put(&mut res, 168, &0xFFFF_FFFFu32.to_be_bytes());
+        put(&mut res, 200, &0xFFFF_FFFFu32.to_be_bytes());
+        put(&mut res, 208, &0xFFFF_FFFFu32.to_be_bytes());
+        put(&mut res, 224, &0xFFFF_FFFFu32.to_be_bytes());
+        put(&mut res, 242, &self.extra_data_flags.to_be_bytes());
+        for &(offset, value) in &self.fields {
+            put(&mut res, offset, &value.to_be_bytes());
+        }
+
+        if !self.exth.is_empty() {
+            put(&mut res, 128, &0x50u32.to_be_bytes());
+            let length: usize = 12 + self.exth.iter().map(|(_, data)| data.len() + 8).sum::<usize>();
+            res.extend_from_slice(b"EXTH");
+            res.extend_from_slice(&(length as u32).to_be_bytes());
+            res.extend_from_slice(&(self.exth.len() as u32).to_be_bytes());
+            for (id, data) in &self.exth {
+                res.extend_from_slice(&id.to_be_bytes());
+                res.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+                res.extend_from_slice(data);
+            }
+        }
+
+        let name_offset = res.len() as u32;
+        put(&mut res, 84, &name_offset.to_be_bytes());
+        put(&mut res, 88, &(self.name.len() as u32).to_be_bytes());
+        res.extend_from_slice(self.name.as_bytes());
+        res.extend_from_slice(&[0, 0]);
+        res
+    }
+}
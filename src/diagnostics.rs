@@ -0,0 +1,158 @@
+use std::fmt;
+use std::io;
+use std::slice;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of an anomaly found while parsing a book
+pub enum DiagnosticKind {
+    /// Offsets in the PDB record list don't increase
+    UnorderedRecords,
+    /// A record starts past the end of the file
+    RecordOutOfBounds,
+    /// A record couldn't be read at all and was left empty
+    UnreadableRecord,
+    /// Trailing entries claim to be larger than the record holding them
+    InvalidTrailingEntries,
+    /// LZ77 back reference pointing outside of decompressed text, skipped while decompressing a record
+    InvalidBackReference,
+    /// LZ77 literal run reaching past the end of a record, cut short while decompressing it
+    TruncatedLiteral,
+    /// Text encoding other than CP1252 and UTF-8, read as CP1252
+    UnknownTextEncoding,
+    /// EXTH record with a length too short to hold its own header, ending parsing of the EXTH header
+    InvalidExthRecord,
+    /// KF8 headers of a joint MOBI/KF8 file past the end of the file
+    TruncatedKf8,
+    /// KF8 boundary in EXTH pointing past the records of the book, read as a file without KF8 part
+    InvalidKf8Boundary,
+    /// A record ends past the end of the file and was cut short
+    TruncatedRecord,
+    /// A count of records in headers exceeds records of the book
+    InvalidRecordCount,
+    /// FONT record which couldn't be unpacked, skipped when reading fonts
+    InvalidFont,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Header in which an anomaly was found
+pub enum HeaderKind {
+    Pdb,
+    Records,
+    PalmDoc,
+    Mobi,
+    Exth,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An anomaly found while parsing a book, which was recovered from by skipping
+/// or guessing part of its content.
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    /// Header containing the anomaly, if it's found in one
+    pub header: Option<HeaderKind>,
+    /// Index of the PDB record containing the anomaly
+    pub record: Option<usize>,
+    /// Offset of the anomaly from the beginning of the file, or of the record
+    /// containing it if the exact location is unknown
+    pub offset: Option<u64>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(kind: DiagnosticKind, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            kind,
+            header: None,
+            record: None,
+            offset: None,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn header(mut self, header: HeaderKind) -> Diagnostic {
+        self.header = Some(header);
+        self
+    }
+
+    pub(crate) fn record(mut self, record: usize) -> Diagnostic {
+        self.record = Some(record);
+        self
+    }
+
+    pub(crate) fn offset(mut self, offset: u64) -> Diagnostic {
+        self.offset = Some(offset);
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(record) = self.record {
+            write!(f, "record {}: ", record)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, "offset {}: ", offset)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<Diagnostic> for io::Error {
+    fn from(diagnostic: Diagnostic) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, diagnostic.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// List of anomalies found while parsing a book. It's always empty with strict
+/// parsing, which fails on the first anomaly instead.
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns whether an anomaly of passed kind was found
+    pub fn contains(&self, kind: DiagnosticKind) -> bool {
+        self.0.iter().any(|d| d.kind == kind)
+    }
+
+    pub(crate) fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub(crate) fn extend(&mut self, other: Diagnostics) {
+        self.0.extend(other.0);
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
@@ -0,0 +1,312 @@
+use crate::headers::NULL_INDEX;
+use crate::index::Index;
+use crate::inflection::{self, Inflection};
+use crate::Mobi;
+use std::collections::HashMap;
+use std::io;
+
+/// Tag of orthographic index entries holding the offset of the definition
+const START_TAG: u8 = 0x01;
+/// Tag of orthographic index entries holding the length of the definition
+const LENGTH_TAG: u8 = 0x02;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Headword of a dictionary, with location of its definition in the text of the book
+pub struct DictionaryEntry {
+    pub headword: String,
+    /// Offset of the definition in the uncompressed text
+    pub start: usize,
+    /// Length of the definition in bytes
+    pub length: usize,
+    /// Inflected forms of the headword, resolved to this entry by lookups
+    pub inflections: Vec<Inflection>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Dictionary described by the orthographic index of a MOBI dictionary,
+/// resolving headwords to HTML of their definitions
+pub struct Dictionary {
+    entries: Vec<DictionaryEntry>,
+    text: Vec<u8>,
+    /// Indexes of entries by lowercase headwords
+    headwords: HashMap<String, Vec<usize>>,
+    /// Indexes of entries by lowercase inflected forms
+    forms: HashMap<String, Vec<usize>>,
+}
+
+impl Dictionary {
+    fn new(entries: Vec<DictionaryEntry>, text: Vec<u8>) -> Dictionary {
+        let mut headwords: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut forms: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            headwords.entry(entry.headword.to_lowercase()).or_default().push(i);
+            for inflection in &entry.inflections {
+                let indexes = forms.entry(inflection.form.to_lowercase()).or_default();
+                if indexes.last() != Some(&i) {
+                    indexes.push(i);
+                }
+            }
+        }
+        Dictionary {
+            entries,
+            text,
+            headwords,
+            forms,
+        }
+    }
+
+    /// Returns the number of entries in the dictionary
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the dictionary has no entries
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entry of `word`, ignoring case like Kindle devices do unless
+    /// there is an entry of the exact word. Words which aren't headwords are
+    /// resolved to entries they are an inflected form of.
+    pub fn entry(&self, word: &str) -> Option<&DictionaryEntry> {
+        let lowercase = word.to_lowercase();
+        self.find(&self.headwords, &lowercase, |entry| entry.headword == word)
+            .or_else(|| {
+                self.find(&self.forms, &lowercase, |entry| {
+                    entry.inflections.iter().any(|inflection| inflection.form == word)
+                })
+            })
+    }
+
+    fn find(
+        &self,
+        words: &HashMap<String, Vec<usize>>,
+        lowercase: &str,
+        exact: impl Fn(&DictionaryEntry) -> bool,
+    ) -> Option<&DictionaryEntry> {
+        let matches = words.get(lowercase)?;
+        let exact_match = matches.iter().find(|&&i| exact(&self.entries[i]));
+        exact_match.or_else(|| matches.first()).map(|&i| &self.entries[i])
+    }
+
+    /// Returns HTML of the definition of `word`, found the same way as by `Dictionary::entry`
+    pub fn lookup(&self, word: &str) -> Option<&[u8]> {
+        self.entry(word).map(|entry| self.definition(entry))
+    }
+
+    /// Returns HTML of the definition of `entry`, cut short if it exceeds the text of the book
+    pub fn definition(&self, entry: &DictionaryEntry) -> &[u8] {
+        let start = entry.start.min(self.text.len());
+        let end = entry.start.saturating_add(entry.length).min(self.text.len());
+        &self.text[start..end]
+    }
+
+    /// Returns all entries with their definitions, in the order of the index
+    pub fn entries(&self) -> impl Iterator<Item = (&DictionaryEntry, &[u8])> {
+        self.entries.iter().map(move |entry| (entry, self.definition(entry)))
+    }
+}
+
+impl<'a> Mobi<'a> {
+    /// Returns the dictionary of a MOBI dictionary, or `None` if the book doesn't
+    /// have an orthographic index. Inflected forms of headwords are decoded from
+    /// the inflection index, if there is one. Returns an error if an index is invalid.
+    pub fn dictionary(&self) -> io::Result<Option<Dictionary>> {
+        let orthographic_index = self.metadata.mobi.orthographic_index;
+        if orthographic_index == NULL_INDEX {
+            return Ok(None);
+        }
+
+        let index = Index::parse(self, orthographic_index as usize)?;
+        let inflection_index = match self.metadata.mobi.inflection_index {
+            NULL_INDEX => None,
+            record => Some(Index::parse(self, record as usize)?),
+        };
+
+        let mut entries = Vec::with_capacity(index.entries.len());
+        let mut lengths = Vec::with_capacity(index.entries.len());
+        for entry in &index.entries {
+            let start = match entry.tag(START_TAG).and_then(|values| values.first()) {
+                Some(&start) => start,
+                None => continue,
+            };
+            let length = entry.tag(LENGTH_TAG).and_then(|values| values.first()).copied();
+            let inflections = match &inflection_index {
+                Some(inflection_index) => inflection::inflections(&index, inflection_index, entry)?,
+                None => Vec::new(),
+            };
+            entries.push(DictionaryEntry {
+                headword: index.decode_label(&entry.label),
+                start: start as usize,
+                length: 0,
+                inflections,
+            });
+            lengths.push(length);
+        }
+
+        // Definitions without a length end where the next one starts, like
+        // KindleUnpack reads them, or at the end of the text
+        let text = self.content()?;
+        let mut starts: Vec<usize> = entries.iter().map(|entry| entry.start).collect();
+        starts.sort_unstable();
+        for (entry, length) in entries.iter_mut().zip(lengths) {
+            entry.length = match length {
+                Some(length) => length as usize,
+                None => {
+                    let next = starts.get(starts.partition_point(|&start| start <= entry.start));
+                    next.copied().unwrap_or_else(|| text.len().max(entry.start)) - entry.start
+                }
+            };
+        }
+        Ok(Some(Dictionary::new(entries, text)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+    use crate::index::tests as index;
+
+    /// Offsets of indexes of the dictionary in record 0
+    const ORTHOGRAPHIC_INDEX: usize = 40;
+    const INFLECTION_INDEX: usize = 44;
+
+    /// Returns a dictionary book with definitions of entries pointed to by the
+    /// orthographic index, which starts at record 2, followed by the inflection
+    /// index if its entries are passed
+    fn dictionary_book(entries: &[Vec<u8>], inflections: Option<(&[Vec<u8>], Vec<u8>)>) -> Vec<u8> {
+        let text = b"<b>run</b> to move fast<b>Run</b> a surname<b>set</b> a group";
+        let inflection_index = if inflections.is_some() { 4 } else { NULL_INDEX };
+        let record0 = book::Record0 {
+            record_count: 1,
+            text_length: text.len() as u32,
+            first_non_book_index: 2,
+            fields: vec![(ORTHOGRAPHIC_INDEX, 2), (INFLECTION_INDEX, inflection_index)],
+            ..Default::default()
+        };
+        let mut records = vec![record0.build(), text.to_vec()];
+        let tags = [[1, 1, 1, 0], [2, 1, 2, 0], [0x2A, 1, 0x0C, 0]];
+        records.extend(index::records(&tags, entries, &[], None));
+        if let Some((entries, cncx)) = inflections {
+            records.extend(index::records(
+                &[[5, 1, 0x03, 0], [0x1A, 1, 0x0C, 0]],
+                entries,
+                &[cncx],
+                None,
+            ));
+        }
+        book::pdb(&records, 2)
+    }
+
+    #[test]
+    fn looks_up_headwords() {
+        let entries = [
+            index::entry(b"run", b"\x03\x80\x97"),
+            index::entry(b"Run", b"\x03\x97\x94"),
+            index::entry(b"set", b"\x03\xab\x92"),
+            index::entry(b"see also", b"\x00"),
+        ];
+        let book = dictionary_book(&entries, None);
+        let dictionary = Mobi::new(&book).unwrap().dictionary().unwrap().unwrap();
+
+        assert_eq!(dictionary.len(), 3);
+        assert_eq!(dictionary.lookup("run"), Some(&b"<b>run</b> to move fast"[..]));
+        assert_eq!(dictionary.lookup("Run"), Some(&b"<b>Run</b> a surname"[..]));
+        assert_eq!(dictionary.lookup("SET"), Some(&b"<b>set</b> a group"[..]));
+        assert_eq!(dictionary.lookup("walk"), None);
+        assert_eq!(
+            dictionary.entry("set"),
+            Some(&DictionaryEntry {
+                headword: String::from("set"),
+                start: 43,
+                length: 18,
+                inflections: vec![],
+            })
+        );
+
+        let headwords: Vec<_> = dictionary.entries().map(|(e, _)| e.headword.as_str()).collect();
+        assert_eq!(headwords, vec!["run", "Run", "set"]);
+    }
+
+    #[test]
+    fn handles_books_without_dictionary() {
+        let record0 = book::Record0 {
+            record_count: 1,
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), b"text".to_vec()], 2);
+        assert!(Mobi::new(&book).unwrap().dictionary().unwrap().is_none());
+
+        // Definitions past the end of the text are cut short
+        let book = dictionary_book(&[index::entry(b"far", b"\x03\xba\x8a")], None);
+        let dictionary = Mobi::new(&book).unwrap().dictionary().unwrap().unwrap();
+        assert_eq!(dictionary.lookup("far"), Some(&b"oup"[..]));
+
+        let mut book = dictionary_book(&[], None);
+        let pos = book.windows(4).position(|w| w == b"TAGX").unwrap();
+        book[pos] = b'X';
+        assert!(Mobi::new(&book).unwrap().dictionary().is_err());
+    }
+
+    #[test]
+    fn resolves_inflected_forms() {
+        let entries = [
+            // Groups 0 and 1 of the inflection index
+            index::entry(b"run", b"\x0b\x80\x97\x80\x81"),
+            index::entry(b"Run", b"\x03\x97\x94"),
+            index::entry(b"set", b"\x07\xab\x92\x81"),
+        ];
+        let inflections = [
+            index::entry(b"", b"\x0a\x80\x8b\x82\x83"),
+            index::entry(b"", b"\x05\x80\x84"),
+            index::entry(b"\x02gnin", b"\x00"),
+            index::entry(b"\x0b\x03u\x02a", b"\x00"),
+            index::entry(b"\x03x", b"\x00"),
+        ];
+        let cncx = b"\x8aparticiple\x84past".to_vec();
+        let book = dictionary_book(&entries, Some((&inflections, cncx)));
+        let dictionary = Mobi::new(&book).unwrap().dictionary().unwrap().unwrap();
+
+        let run = dictionary.entry("run").unwrap();
+        assert_eq!(
+            run.inflections,
+            vec![
+                Inflection {
+                    name: String::from("participle"),
+                    form: String::from("running"),
+                },
+                Inflection {
+                    name: String::from("past"),
+                    form: String::from("ran"),
+                },
+            ]
+        );
+        // Rule of group 1 doesn't apply to "set"
+        assert!(dictionary.entry("set").unwrap().inflections.is_empty());
+
+        assert_eq!(dictionary.lookup("running"), Some(&b"<b>run</b> to move fast"[..]));
+        assert_eq!(dictionary.lookup("Ran"), Some(&b"<b>run</b> to move fast"[..]));
+        assert_eq!(dictionary.lookup("Run"), Some(&b"<b>Run</b> a surname"[..]));
+        assert_eq!(dictionary.lookup("sets"), None);
+
+        let inflections = [index::entry(b"", b"\x05\x80\x89")];
+        let book = dictionary_book(&entries, Some((&inflections, vec![])));
+        assert!(Mobi::new(&book).unwrap().dictionary().is_err());
+    }
+
+    #[test]
+    fn ends_definitions_without_length_at_next_entry() {
+        let entries = [
+            index::entry(b"run", b"\x01\x80"),
+            index::entry(b"Run", b"\x03\x97\x94"),
+            index::entry(b"set", b"\x01\xab"),
+        ];
+        let book = dictionary_book(&entries, None);
+        let dictionary = Mobi::new(&book).unwrap().dictionary().unwrap().unwrap();
+
+        assert_eq!(dictionary.lookup("run"), Some(&b"<b>run</b> to move fast"[..]));
+        assert_eq!(dictionary.lookup("set"), Some(&b"<b>set</b> a group"[..]));
+        assert_eq!(dictionary.entry("set").unwrap().length, 18);
+    }
+}
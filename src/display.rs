@@ -127,6 +127,8 @@ Mobi type:              {}
 Text encoding:          {}
 Id:                     {}
 Gen version:            v{}
+Orthographic index:     {}
+Inflection index:       {}
 First non book index:   {}
 Name:                   {}
 Name offset:            {}
@@ -147,15 +149,28 @@ DRM offset:             {}
 DRM count:              {}
 DRM size:               {}
 DRM flags:              {}
-Last image record:      {}
+First content record:   {}
+Last content record:    {}
+FDST index:             {}
+FDST count:             {}
 Fcis record:            {}
-Flis record:            {}",
+Flis record:            {}
+SRCS index:             {}
+SRCS count:             {}
+Extra data flags:       {}
+NCX index:              {}
+Fragment index:         {}
+Skeleton index:         {}
+DATP index:             {}
+Guide index:            {}",
             self.identifier,
             self.header_length,
-            self.mobi_type().unwrap_or_default(),
+            self.mobi_type(),
             self.text_encoding(),
             self.id,
             self.gen_version,
+            self.orthographic_index,
+            self.inflection_index,
             self.first_non_book_index,
             self.name,
             self.name_offset,
@@ -176,14 +191,25 @@ Flis record:            {}",
             self.drm_count,
             self.drm_size,
             self.drm_flags,
-            self.last_image_record,
+            self.first_content_record,
+            self.last_content_record,
+            self.fdst_index,
+            self.fdst_count,
             self.fcis_record,
             self.flis_record,
+            self.srcs_index,
+            self.srcs_count,
+            self.extra_data_flags,
+            self.ncx_index,
+            self.fragment_index,
+            self.skeleton_index,
+            self.datp_index,
+            self.guide_index,
         )
     }
 }
 
-impl fmt::Display for Mobi {
+impl fmt::Display for Mobi<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
@@ -0,0 +1,297 @@
+use crate::options::ParseOptions;
+use crate::{Diagnostic, DiagnosticKind, Diagnostics, Mobi, RecordKind};
+#[cfg(feature = "deflate")]
+use flate2::read::ZlibDecoder;
+use std::io;
+#[cfg(feature = "deflate")]
+use std::io::Read;
+
+const FONT_MAGIC: &[u8] = b"FONT";
+const FONT_HEADER_LEN: usize = 24;
+/// Only the leading part of font data is obfuscated
+const XOR_LEN: usize = 1040;
+const ZLIB_FLAG: u32 = 0x1;
+const XOR_FLAG: u32 = 0x2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Format of an embedded font, detected from its leading bytes
+pub enum FontFormat {
+    /// TrueType font or collection
+    TrueType,
+    /// OpenType font with CFF outlines
+    OpenType,
+    Unknown,
+}
+
+impl FontFormat {
+    fn detect(data: &[u8]) -> FontFormat {
+        match data.get(..4) {
+            Some(b"\0\x01\0\0") | Some(b"true") | Some(b"ttcf") => FontFormat::TrueType,
+            Some(b"OTTO") => FontFormat::OpenType,
+            _ => FontFormat::Unknown,
+        }
+    }
+
+    /// Returns the usual file extension of fonts of this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            FontFormat::TrueType => "ttf",
+            FontFormat::OpenType => "otf",
+            FontFormat::Unknown => "dat",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A font embedded in a FONT record, as it was before being packed into the book
+pub struct Font {
+    /// Index of the PDB record holding the font
+    pub record: usize,
+    pub format: FontFormat,
+    pub data: Vec<u8>,
+}
+
+impl Font {
+    /// Unpacks the font held by FONT record at `index`, reversing its obfuscation
+    /// and compression
+    pub(crate) fn parse(index: usize, record: &[u8], options: &ParseOptions) -> io::Result<Font> {
+        let invalid =
+            |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("record {}: {}", index, message));
+        if record.len() < FONT_HEADER_LEN || !record.starts_with(FONT_MAGIC) {
+            return Err(invalid("not a valid FONT record"));
+        }
+        let u32_at = |pos: usize| u32::from_be_bytes([record[pos], record[pos + 1], record[pos + 2], record[pos + 3]]);
+        let (size, flags, start) = (u32_at(4) as usize, u32_at(8), u32_at(12) as usize);
+        let (key_len, key_start) = (u32_at(16) as usize, u32_at(20) as usize);
+
+        let mut packed = record
+            .get(start..)
+            .ok_or_else(|| invalid("font data starts past the end of the record"))?
+            .to_vec();
+        if flags & XOR_FLAG != 0 {
+            let key = key_start
+                .checked_add(key_len)
+                .and_then(|end| record.get(key_start..end))
+                .filter(|key| !key.is_empty())
+                .ok_or_else(|| invalid("obfuscation key exceeds the record"))?;
+            for (i, byte) in packed.iter_mut().take(XOR_LEN).enumerate() {
+                *byte ^= key[i % key.len()];
+            }
+        }
+
+        let data = if flags & ZLIB_FLAG != 0 {
+            inflate(&packed, size, options).map_err(|e| match e.kind() {
+                io::ErrorKind::InvalidData => e,
+                _ => invalid(&e.to_string()),
+            })?
+        } else {
+            packed
+        };
+
+        Ok(Font {
+            record: index,
+            format: FontFormat::detect(&data),
+            data,
+        })
+    }
+}
+
+/// Inflates zlib compressed font data, `size` being the size of the font it holds
+#[cfg(feature = "deflate")]
+fn inflate(packed: &[u8], size: usize, options: &ParseOptions) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(options.capacity(size));
+    // Reading a byte past the limit is enough to tell it's exceeded
+    ZlibDecoder::new(packed)
+        .take(options.max_decompressed_size.saturating_add(1))
+        .read_to_end(&mut data)?;
+    options.check_unpacked_size(data.len() as u64)?;
+    Ok(data)
+}
+
+#[cfg(not(feature = "deflate"))]
+fn inflate(_: &[u8], _: usize, _: &ParseOptions) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "unpacking compressed fonts requires feature `deflate`",
+    ))
+}
+
+impl<'a> Mobi<'a> {
+    /// Returns fonts embedded in FONT records of the book, usually KF8 ones,
+    /// ready to be saved as font files. Records which can't be unpacked are
+    /// skipped and reported by `Mobi::diagnostics`, unless parsing is strict.
+    pub fn fonts(&self) -> io::Result<Vec<Font>> {
+        self.read_fonts(&mut Diagnostics::new())
+    }
+
+    pub(crate) fn read_fonts(&self, diagnostics: &mut Diagnostics) -> io::Result<Vec<Font>> {
+        let mut fonts = Vec::new();
+        for (index, &kind) in self.kinds().iter().enumerate() {
+            if kind != RecordKind::Font {
+                continue;
+            }
+            let record = self.raw_record(index);
+            let data = record.as_ref().map(|r| r.data).unwrap_or_default();
+            let font = self.options.recover(
+                diagnostics,
+                Font::parse(index, data, &self.options).map(Some),
+                |e| {
+                    let diagnostic = Diagnostic::new(DiagnosticKind::InvalidFont, e.to_string()).record(index);
+                    match &record {
+                        Some(record) => diagnostic.offset(u64::from(record.offset)),
+                        None => diagnostic,
+                    }
+                },
+                || None,
+            )?;
+            fonts.extend(font);
+        }
+        Ok(fonts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+    #[cfg(feature = "deflate")]
+    use crate::LimitExceeded;
+
+    const TTF: &[u8] = b"\0\x01\0\0\0\x0a\0\x80\0\x03\0\x20glyf";
+
+    #[cfg(feature = "deflate")]
+    fn compress(data: &[u8]) -> Vec<u8> {
+        use flate2::{write::ZlibEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    fn compress(data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    /// Packs `data` into a FONT record, compressing it with zlib and obfuscating
+    /// it with `key` if passed
+    fn font_record(data: &[u8], compress: bool, key: Option<&[u8]>) -> Vec<u8> {
+        let mut packed = data.to_vec();
+        if compress {
+            packed = self::compress(data);
+        }
+        let key = key.unwrap_or_default();
+        if !key.is_empty() {
+            for (i, byte) in packed.iter_mut().take(XOR_LEN).enumerate() {
+                *byte ^= key[i % key.len()];
+            }
+        }
+
+        let mut flags = 0;
+        if compress {
+            flags |= ZLIB_FLAG;
+        }
+        if !key.is_empty() {
+            flags |= XOR_FLAG;
+        }
+        let start = FONT_HEADER_LEN + key.len();
+        let mut record = FONT_MAGIC.to_vec();
+        for value in &[
+            data.len() as u32,
+            flags,
+            start as u32,
+            key.len() as u32,
+            FONT_HEADER_LEN as u32,
+        ] {
+            record.extend_from_slice(&value.to_be_bytes());
+        }
+        record.extend_from_slice(key);
+        record.extend_from_slice(&packed);
+        record
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn unpacks_fonts() {
+        let record0 = book::Record0 {
+            first_image_index: 1,
+            ..Default::default()
+        };
+        let records = [
+            record0.build(),
+            font_record(TTF, true, Some(b"\x12\x34\x56")),
+            b"\x89PNG\r\n\x1a\n".to_vec(),
+            font_record(b"OTTO\0\x09", false, None),
+        ];
+        let book = book::pdb(&records, 2);
+        let fonts = Mobi::new(&book).unwrap().fonts().unwrap();
+
+        assert_eq!(
+            fonts,
+            vec![
+                Font {
+                    record: 1,
+                    format: FontFormat::TrueType,
+                    data: TTF.to_vec(),
+                },
+                Font {
+                    record: 3,
+                    format: FontFormat::OpenType,
+                    data: b"OTTO\0\x09".to_vec(),
+                },
+            ]
+        );
+        assert_eq!(fonts[1].format.extension(), "otf");
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn rejects_invalid_fonts() {
+        let options = ParseOptions::new();
+        let mut record = font_record(TTF, true, Some(b"\x12"));
+        // Key offset past the end of the record
+        record[20..24].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(Font::parse(1, &record, &options).is_err());
+        assert!(Font::parse(1, b"FONT", &options).is_err());
+
+        let record = font_record(TTF, true, None);
+        let e = Font::parse(1, &record, &options.max_decompressed_size(4)).unwrap_err();
+        assert!(e.get_ref().unwrap().is::<LimitExceeded>());
+    }
+
+    #[test]
+    fn skips_invalid_fonts() {
+        let record0 = book::Record0 {
+            first_image_index: 1,
+            ..Default::default()
+        };
+        let mut broken = font_record(TTF, true, None);
+        let len = broken.len();
+        broken[len - 1] ^= 0xFF;
+        let records = [record0.build(), broken, font_record(TTF, false, None)];
+        let book = book::pdb(&records, 2);
+
+        let m = Mobi::new(&book).unwrap();
+        let fonts = m.fonts().unwrap();
+        assert_eq!(fonts.iter().map(|f| f.record).collect::<Vec<_>>(), vec![2]);
+        let diagnostics = m.diagnostics().unwrap();
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(
+            (diagnostic.kind, diagnostic.record),
+            (DiagnosticKind::InvalidFont, Some(1))
+        );
+
+        let m = Mobi::from_read_with_options(&book[..], &ParseOptions::new().strict()).unwrap();
+        assert!(m.fonts().is_err());
+    }
+
+    #[cfg(not(feature = "deflate"))]
+    #[test]
+    fn needs_deflate_for_compressed_fonts() {
+        let options = ParseOptions::new();
+        assert!(Font::parse(1, &font_record(TTF, true, None), &options).is_err());
+        let font = Font::parse(1, &font_record(TTF, false, Some(b"\x12")), &options).unwrap();
+        assert_eq!(font.data, TTF);
+    }
+}
@@ -1,9 +1,9 @@
 #![allow(dead_code)]
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, HeaderKind};
 use crate::reader::MobiReader;
+use crate::ParseOptions;
 use std::{collections::HashMap, io};
 
-const RECORDS_OFFSET: u64 = 108;
-
 // Records available in EXTH header
 pub enum ExthRecord {
     // source - https://wiki.mobileread.com/wiki/MOBI#EXTH_Header
@@ -84,10 +84,14 @@ pub struct ExtHeader {
 }
 
 impl ExtHeader {
-    /// Parse a EXTH header from the content
-    pub(crate) fn parse(reader: &mut impl MobiReader, header_length: u32) -> io::Result<ExtHeader> {
-        let header_length = header_length as u64;
-        reader.set_position(reader.position_after_records() + header_length + 96);
+    /// Parse a EXTH header located at `offset` from the content
+    pub(crate) fn parse(
+        reader: &mut impl MobiReader,
+        offset: u64,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<ExtHeader> {
+        reader.set_position(offset);
         let mut extheader = ExtHeader {
             identifier: reader.read_u32_be()?,
             header_length: reader.read_u32_be()?,
@@ -95,17 +99,36 @@ impl ExtHeader {
             records: HashMap::new(),
         };
 
-        extheader.populate_records(reader)?;
+        extheader.populate_records(reader, options, diagnostics)?;
         Ok(extheader)
     }
 
-    /// Gets header records
-    fn populate_records(&mut self, reader: &mut impl MobiReader) -> io::Result<()> {
+    /// Gets header records. Records with length shorter than their own header
+    /// end parsing in lenient mode, as the rest can't be located.
+    fn populate_records(
+        &mut self,
+        reader: &mut impl MobiReader,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<()> {
         for _i in 0..self.record_count {
+            let record_offset = reader.get_position();
             let record_type = reader.read_u32_be()?;
             let record_len = reader.read_u32_be()?;
+            if record_len < 8 {
+                options.check(diagnostics, false, || {
+                    Diagnostic::new(
+                        DiagnosticKind::InvalidExthRecord,
+                        format!("EXTH record {} has invalid length {}", record_type, record_len),
+                    )
+                    .header(HeaderKind::Exth)
+                    .offset(record_offset)
+                })?;
+                break;
+            }
+            options.check_exth_record_size(record_len as usize - 8)?;
 
-            let mut record_data = Vec::with_capacity(record_len as usize - 8);
+            let mut record_data = Vec::with_capacity(options.capacity(record_len as usize - 8));
             for _j in 0..record_len - 8 {
                 record_data.push(reader.read_u8()?);
             }
@@ -127,6 +150,16 @@ impl ExtHeader {
         self.get_record_position(record as u32)
     }
 
+    /// Returns the index of the first record of the KF8 part of a joint MOBI/KF8
+    /// file, if there is one.
+    pub(crate) fn kf8_boundary(&self) -> Option<u32> {
+        self.get_record(ExthRecord::KF8BoundaryOffset)
+            .and_then(|r| match r.as_slice() {
+                [a, b, c, d] => Some(u32::from_be_bytes([*a, *b, *c, *d])),
+                _ => None,
+            })
+    }
+
     pub(crate) fn get_record_string_lossy(&self, record: ExthRecord) -> Option<String> {
         self.get_record(record).map(|r| String::from_utf8_lossy(r).to_string())
     }
@@ -166,14 +199,21 @@ mod tests {
             records,
         };
         let book = book::full_book();
-        let mut reader = Reader::new(&book);
+        let mut reader = Reader::new(&book[..]);
         let h = Header::parse(&mut reader).unwrap();
         reader.set_num_records(h.num_records);
-        let _ = Records::parse(&mut reader).unwrap();
+        let records = Records::parse(&mut reader).unwrap();
+        let record0 = records.records[0].0 as u64;
         let _ = PalmDocHeader::parse(&mut reader).unwrap();
         let mut mobi = MobiHeader::partial_parse(&mut reader).unwrap();
-        let parsed_header = ExtHeader::parse(&mut reader, mobi.header_length).unwrap();
-        mobi.finish_parse(&mut reader).expect("Should find a name.");
+        let parsed_header = ExtHeader::parse(
+            &mut reader,
+            record0 + 16 + mobi.header_length as u64,
+            &ParseOptions::default(),
+            &mut Diagnostics::new(),
+        )
+        .unwrap();
+        mobi.finish_parse(&mut reader, record0).expect("Should find a name.");
         for (k, v) in &extheader.records {
             let record = parsed_header.get_record_position(*k);
             assert!(record.is_some());
@@ -190,14 +230,21 @@ mod tests {
         macro_rules! info {
             ($t: ident, $s: expr) => {
                 let book = book::full_book();
-                let mut reader = Reader::new(&book);
+                let mut reader = Reader::new(&book[..]);
                 let h = Header::parse(&mut reader).unwrap();
                 reader.set_num_records(h.num_records);
-                let _ = Records::parse(&mut reader).unwrap();
+                let records = Records::parse(&mut reader).unwrap();
+                let record0 = records.records[0].0 as u64;
                 let _ = PalmDocHeader::parse(&mut reader).unwrap();
                 let mut mobi = MobiHeader::partial_parse(&mut reader).unwrap();
-                let exth = ExtHeader::parse(&mut reader, mobi.header_length).unwrap();
-                mobi.finish_parse(&mut reader).expect("Should find name");
+                let exth = ExtHeader::parse(
+                    &mut reader,
+                    record0 + 16 + mobi.header_length as u64,
+                    &ParseOptions::default(),
+                    &mut Diagnostics::new(),
+                )
+                .unwrap();
+                mobi.finish_parse(&mut reader, record0).expect("Should find name");
                 let data = exth.get_record_string_lossy(ExthRecord::$t);
                 assert_eq!(data, Some(String::from($s)));
             };
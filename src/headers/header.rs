@@ -3,6 +3,9 @@ use crate::reader::MobiReader;
 use chrono::NaiveDateTime;
 use std::io;
 
+/// Length of the PDB header preceding the record list
+pub(crate) const PDB_HEADER_LEN: usize = 78;
+
 #[derive(Debug, PartialEq, Default)]
 /// Strcture that holds header information
 pub struct Header {
@@ -96,7 +99,7 @@ mod tests {
             next_record_list_id: 0,
             num_records: 292,
         };
-        let mut reader = Reader::new(&book::HEADER);
+        let mut reader = Reader::new(&book::HEADER[..]);
         let parsed_header = Header::parse(&mut reader);
         assert_eq!(header, parsed_header.unwrap())
     }
@@ -1,15 +1,117 @@
 use crate::reader::MobiReader;
-use std::io;
+use std::{fmt, io};
 
 const DRM_ON_FLAG: u32 = 0xFFFF_FFFF;
 const EXTH_ON_FLAG: u32 = 0x40;
+/// Value of an index field that doesn't point to any record.
+pub const NULL_INDEX: u32 = 0xFFFF_FFFF;
+/// Length of MOBI headers of KF8 files, which end with KF8 only fields
+const KF8_HEADER_LEN: u32 = 0x108;
 
-#[derive(Debug, PartialEq)]
+/// Offsets of MOBI header fields from the beginning of the header, in the order
+/// `MobiHeader::partial_parse` reads them
+pub(crate) mod offsets {
+    pub(crate) const HEADER_LENGTH: usize = 4;
+    pub(crate) const ORTHOGRAPHIC_INDEX: usize = 24;
+    pub(crate) const INFLECTION_INDEX: usize = 28;
+    pub(crate) const INDEX_NAMES: usize = 32;
+    pub(crate) const INDEX_KEYS: usize = 36;
+    pub(crate) const EXTRA_INDEX0: usize = 40;
+    pub(crate) const EXTRA_INDEX5: usize = 60;
+    pub(crate) const FIRST_NON_BOOK_INDEX: usize = 64;
+    pub(crate) const NAME_OFFSET: usize = 68;
+    pub(crate) const NAME_LENGTH: usize = 72;
+    pub(crate) const FORMAT_VERSION: usize = 88;
+    pub(crate) const FIRST_IMAGE_INDEX: usize = 92;
+    pub(crate) const FIRST_HUFF_RECORD: usize = 96;
+    pub(crate) const HUFF_RECORD_COUNT: usize = 100;
+    pub(crate) const FIRST_DATA_RECORD: usize = 104;
+    pub(crate) const DATA_RECORD_COUNT: usize = 108;
+    pub(crate) const EXTH_FLAGS: usize = 112;
+    pub(crate) const DRM_OFFSET: usize = 152;
+    pub(crate) const DRM_COUNT: usize = 156;
+    pub(crate) const DRM_FLAGS: usize = 164;
+    /// Both FDST index of KF8 headers and u16 first content record of older ones
+    pub(crate) const FDST_INDEX: usize = 176;
+    pub(crate) const FIRST_CONTENT_RECORD: usize = 176;
+    pub(crate) const LAST_CONTENT_RECORD: usize = 178;
+    pub(crate) const FDST_COUNT: usize = 180;
+    pub(crate) const FCIS_RECORD: usize = 184;
+    pub(crate) const FCIS_COUNT: usize = 188;
+    pub(crate) const FLIS_RECORD: usize = 192;
+    pub(crate) const FLIS_COUNT: usize = 196;
+    pub(crate) const SRCS_INDEX: usize = 208;
+    pub(crate) const SRCS_COUNT: usize = 212;
+    pub(crate) const EXTRA_DATA_FLAGS: usize = 226;
+    pub(crate) const NCX_INDEX: usize = 228;
+    pub(crate) const FRAGMENT_INDEX: usize = 232;
+    pub(crate) const SKELETON_INDEX: usize = 236;
+    pub(crate) const DATP_INDEX: usize = 240;
+    pub(crate) const GUIDE_INDEX: usize = 244;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum TextEncoding {
     CP1252,
     UTF8,
 }
 
+/// Types of books available in MOBI format.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MobiType {
+    MobipocketBook,
+    PalmDocBook,
+    Audio,
+    News,
+    NewsFeed,
+    NewsMagazine,
+    PICS,
+    WORD,
+    XLS,
+    PPT,
+    TEXT,
+    HTML,
+    Unknown(u32),
+}
+impl From<u32> for MobiType {
+    fn from(n: u32) -> MobiType {
+        match n {
+            2 => MobiType::MobipocketBook,
+            3 => MobiType::PalmDocBook,
+            4 => MobiType::Audio,
+            257 => MobiType::News,
+            258 => MobiType::NewsFeed,
+            259 => MobiType::NewsMagazine,
+            513 => MobiType::PICS,
+            514 => MobiType::WORD,
+            515 => MobiType::XLS,
+            516 => MobiType::PPT,
+            517 => MobiType::TEXT,
+            518 => MobiType::HTML,
+            n => MobiType::Unknown(n),
+        }
+    }
+}
+impl fmt::Display for MobiType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MobiType::MobipocketBook => write!(f, "Mobipocket Book"),
+            MobiType::PalmDocBook => write!(f, "PalmDoc Book"),
+            MobiType::Audio => write!(f, "Audio"),
+            MobiType::News => write!(f, "News"),
+            MobiType::NewsFeed => write!(f, "News Feed"),
+            MobiType::NewsMagazine => write!(f, "News Magazine"),
+            MobiType::PICS => write!(f, "PICS"),
+            MobiType::WORD => write!(f, "WORD"),
+            MobiType::XLS => write!(f, "XLS"),
+            MobiType::PPT => write!(f, "PPT"),
+            MobiType::TEXT => write!(f, "TEXT"),
+            MobiType::HTML => write!(f, "HTML"),
+            MobiType::Unknown(n) => write!(f, "Unknown ({})", n),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Default)]
 /// Strcture that holds Mobi header information
 pub struct MobiHeader {
@@ -19,6 +121,16 @@ pub struct MobiHeader {
     pub text_encoding: u32,
     pub id: u32,
     pub gen_version: u32,
+    pub orthographic_index: u32,
+    pub inflection_index: u32,
+    pub index_names: u32,
+    pub index_keys: u32,
+    pub extra_index0: u32,
+    pub extra_index1: u32,
+    pub extra_index2: u32,
+    pub extra_index3: u32,
+    pub extra_index4: u32,
+    pub extra_index5: u32,
     pub first_non_book_index: u32,
     pub name: String,
     pub name_offset: u32,
@@ -33,67 +145,155 @@ pub struct MobiHeader {
     pub first_data_record: u32,
     pub data_record_count: u32,
     pub exth_flags: u32,
+    pub unknown0: [u8; 32],
+    pub unknown1: u32,
     pub drm_offset: u32,
     pub drm_count: u32,
     pub drm_size: u32,
     pub drm_flags: u32,
+    pub unknown2: u64,
+    /// First content record, only available in files older than KF8
+    pub first_content_record: u16,
+    /// Last content record, only available in files older than KF8
+    pub last_content_record: u16,
+    /// Last content record, only available in files older than KF8
+    #[deprecated(note = "use `last_content_record` instead")]
     pub last_image_record: u16,
+    /// FDST record, only available in KF8 files
+    pub fdst_index: u32,
+    /// Number of FDST sections, only available in KF8 files
+    pub fdst_count: u32,
     pub fcis_record: u32,
+    pub fcis_count: u32,
     pub flis_record: u32,
+    pub flis_count: u32,
+    pub unknown3: u64,
+    pub srcs_index: u32,
+    pub srcs_count: u32,
+    pub unknown4: u32,
+    pub unknown5: u32,
+    pub unknown6: u16,
+    pub extra_data_flags: u16,
+    pub ncx_index: u32,
+    /// Fragment index, only available in KF8 files
+    pub fragment_index: u32,
+    /// Skeleton index, only available in KF8 files
+    pub skeleton_index: u32,
+    pub datp_index: u32,
+    /// Guide index, only available in KF8 files
+    pub guide_index: u32,
+    pub unknown7: u32,
+    pub unknown8: u32,
+    pub unknown9: u32,
+    pub unknown10: u32,
 }
 
 impl MobiHeader {
-    /// Partially parse a Mobi header from the content
+    /// Partially parse a Mobi header from the content. Fields that lie past the
+    /// end of the header as declared by `header_length` are not read and are
+    /// left with their default (or `NULL_INDEX` for record indexes) values.
     pub(crate) fn partial_parse(reader: &mut impl MobiReader) -> io::Result<MobiHeader> {
-        Ok(MobiHeader {
-            identifier: reader.read_u32_be()?,
-            header_length: reader.read_u32_be()?,
-            mobi_type: reader.read_u32_be()?,
-            text_encoding: reader.read_u32_be()?,
-            id: reader.read_u32_be()?,
-            gen_version: reader.read_u32_be()?,
-            first_non_book_index: {
-                reader.set_position(reader.get_position() + 40);
-                reader.read_u32_be()?
-            },
-            name_offset: reader.read_u32_be()?,
-            name_length: reader.read_u32_be()?,
-            language_code: MobiHeader::lang_code(reader.read_u32_be()?),
-            input_language: reader.read_u32_be()?,
-            output_language: reader.read_u32_be()?,
-            format_version: reader.read_u32_be()?,
-            first_image_index: reader.read_u32_be()?,
-            first_huff_record: reader.read_u32_be()?,
-            huff_record_count: reader.read_u32_be()?,
-            first_data_record: reader.read_u32_be()?,
-            data_record_count: reader.read_u32_be()?,
-            exth_flags: reader.read_u32_be()?,
-            drm_offset: {
-                reader.set_position(reader.get_position() + 36);
-                reader.read_u32_be()?
-            },
-            drm_count: reader.read_u32_be()?,
-            drm_size: reader.read_u32_be()?,
-            drm_flags: reader.read_u32_be()?,
-            last_image_record: {
-                reader.set_position(reader.get_position() + 10);
-                reader.read_u16_be()?
-            },
-            fcis_record: {
-                reader.read_u32_be()?;
-                reader.read_u32_be()?
-            },
-            flis_record: {
-                reader.read_u32_be()?;
-                reader.read_u32_be()?
-            },
-            name: String::new(),
-        })
+        let start = reader.get_position();
+        let identifier = reader.read_u32_be()?;
+        let header_length = reader.read_u32_be()?;
+        let mut h = HeaderFields {
+            reader,
+            end: start + u64::from(header_length),
+        };
+
+        let mut header = MobiHeader {
+            identifier,
+            header_length,
+            mobi_type: h.u32(0)?,
+            text_encoding: h.u32(0)?,
+            id: h.u32(0)?,
+            gen_version: h.u32(0)?,
+            orthographic_index: h.u32(NULL_INDEX)?,
+            inflection_index: h.u32(NULL_INDEX)?,
+            index_names: h.u32(NULL_INDEX)?,
+            index_keys: h.u32(NULL_INDEX)?,
+            extra_index0: h.u32(NULL_INDEX)?,
+            extra_index1: h.u32(NULL_INDEX)?,
+            extra_index2: h.u32(NULL_INDEX)?,
+            extra_index3: h.u32(NULL_INDEX)?,
+            extra_index4: h.u32(NULL_INDEX)?,
+            extra_index5: h.u32(NULL_INDEX)?,
+            first_non_book_index: h.u32(NULL_INDEX)?,
+            name_offset: h.u32(0)?,
+            name_length: h.u32(0)?,
+            language_code: MobiHeader::lang_code(h.u32(0)?),
+            input_language: h.u32(0)?,
+            output_language: h.u32(0)?,
+            format_version: h.u32(0)?,
+            first_image_index: h.u32(NULL_INDEX)?,
+            first_huff_record: h.u32(0)?,
+            huff_record_count: h.u32(0)?,
+            first_data_record: h.u32(0)?,
+            data_record_count: h.u32(0)?,
+            exth_flags: h.u32(0)?,
+            unknown0: h.bytes()?,
+            unknown1: h.u32(0)?,
+            drm_offset: h.u32(DRM_ON_FLAG)?,
+            drm_count: h.u32(0)?,
+            drm_size: h.u32(0)?,
+            drm_flags: h.u32(0)?,
+            unknown2: h.u64(0)?,
+            ..Default::default()
+        };
+
+        if header.is_kf8() {
+            header.fdst_index = h.u32(NULL_INDEX)?;
+        } else {
+            header.first_content_record = h.u16(0)?;
+            header.last_content_record = h.u16(0)?;
+            #[allow(deprecated)]
+            {
+                header.last_image_record = header.last_content_record;
+            }
+            header.fdst_index = NULL_INDEX;
+        }
+        // Files older than KF8 hold an unknown value there, always 1
+        let fdst_count = h.u32(0)?;
+        if header.is_kf8() || header.header_length >= KF8_HEADER_LEN {
+            header.fdst_count = fdst_count;
+        }
+        header.fcis_record = h.u32(NULL_INDEX)?;
+        header.fcis_count = h.u32(0)?;
+        header.flis_record = h.u32(NULL_INDEX)?;
+        header.flis_count = h.u32(0)?;
+        header.unknown3 = h.u64(0)?;
+        header.srcs_index = h.u32(NULL_INDEX)?;
+        header.srcs_count = h.u32(0)?;
+        header.unknown4 = h.u32(0)?;
+        header.unknown5 = h.u32(0)?;
+        header.unknown6 = h.u16(0)?;
+        header.extra_data_flags = h.u16(0)?;
+        header.ncx_index = h.u32(NULL_INDEX)?;
+        if header.is_kf8() {
+            header.fragment_index = h.u32(NULL_INDEX)?;
+            header.skeleton_index = h.u32(NULL_INDEX)?;
+        } else {
+            header.unknown7 = h.u32(0)?;
+            header.unknown8 = h.u32(0)?;
+            header.fragment_index = NULL_INDEX;
+            header.skeleton_index = NULL_INDEX;
+        }
+        header.datp_index = h.u32(NULL_INDEX)?;
+        if header.is_kf8() {
+            header.guide_index = h.u32(NULL_INDEX)?;
+        } else {
+            header.unknown9 = h.u32(0)?;
+            header.guide_index = NULL_INDEX;
+        }
+        header.unknown10 = h.u32(0)?;
+
+        Ok(header)
     }
 
-    pub(crate) fn finish_parse(&mut self, reader: &mut impl MobiReader) -> io::Result<()> {
-        // TODO: figure out why is this exactly `+ 80` and it works?
-        let offset = reader.position_after_records() + 80 + self.name_offset as u64;
+    /// Reads the full name of the book, `record0` being the offset of the record
+    /// this header belongs to, as the name offset is relative to it.
+    pub(crate) fn finish_parse(&mut self, reader: &mut impl MobiReader, record0: u64) -> io::Result<()> {
+        let offset = record0 + u64::from(self.name_offset);
         self.name = reader.read_string_header(offset, self.name_length as usize)?;
         Ok(())
     }
@@ -103,45 +303,35 @@ impl MobiHeader {
         (self.exth_flags & EXTH_ON_FLAG) != 0
     }
 
+    /// Checks if this header describes a KF8 (format version 8 or later) book
+    pub fn is_kf8(&self) -> bool {
+        self.format_version >= 8
+    }
+
     /// Checks if there is DRM on this book
+    #[cfg_attr(not(feature = "fmt"), allow(dead_code))]
     pub(crate) fn has_drm(&self) -> bool {
         self.drm_offset != DRM_ON_FLAG
     }
 
     /// Converts numerical value into a type
-    pub(crate) fn mobi_type(&self) -> Option<String> {
-        macro_rules! mtype {
-            ($s:expr) => {
-                Some(String::from($s))
-            };
-        }
-        match self.mobi_type {
-            2 => mtype!("Mobipocket Book"),
-            3 => mtype!("PalmDoc Book"),
-            4 => mtype!("Audio"),
-            257 => mtype!("News"),
-            258 => mtype!("News Feed"),
-            259 => mtype!("News Magazine"),
-            513 => mtype!("PICS"),
-            514 => mtype!("WORD"),
-            515 => mtype!("XLS"),
-            516 => mtype!("PPT"),
-            517 => mtype!("TEXT"),
-            518 => mtype!("HTML"),
-            _ => None,
-        }
+    pub(crate) fn mobi_type(&self) -> MobiType {
+        MobiType::from(self.mobi_type)
     }
 
-    // Mobi format only specifies this two encodings so
-    // this should never panic
+    // Mobi format only specifies this two encodings, unknown ones are
+    // read as CP1252 like most readers do
     pub(crate) fn text_encoding(&self) -> TextEncoding {
         match self.text_encoding {
-            1252 => TextEncoding::CP1252,
             65001 => TextEncoding::UTF8,
-            n => panic!("Unknown encoding {}", n),
+            _ => TextEncoding::CP1252,
         }
     }
 
+    pub(crate) fn has_known_encoding(&self) -> bool {
+        self.text_encoding == 1252 || self.text_encoding == 65001
+    }
+
     fn lang_code(code: u32) -> u16 {
         (code & 0xFF) as u16
     }
@@ -233,12 +423,70 @@ impl MobiHeader {
         }
     }
 }
+/// Reads consecutive header fields, falling back to a default value for
+/// fields lying beyond the declared end of the header. Once a field doesn't
+/// fit, the reader is moved to the end of the header and no further fields
+/// are read, so smaller fields don't pick up bytes of the truncated one.
+struct HeaderFields<'r, R> {
+    reader: &'r mut R,
+    end: u64,
+}
+
+impl<'r, R: MobiReader> HeaderFields<'r, R> {
+    fn available(&mut self, n: u64) -> bool {
+        let position = self.reader.get_position();
+        if position + n <= self.end {
+            return true;
+        }
+        if position < self.end {
+            self.reader.set_position(self.end);
+        }
+        false
+    }
+
+    fn u16(&mut self, default: u16) -> io::Result<u16> {
+        if self.available(2) {
+            self.reader.read_u16_be()
+        } else {
+            Ok(default)
+        }
+    }
+
+    fn u32(&mut self, default: u32) -> io::Result<u32> {
+        if self.available(4) {
+            self.reader.read_u32_be()
+        } else {
+            Ok(default)
+        }
+    }
+
+    fn u64(&mut self, default: u64) -> io::Result<u64> {
+        if self.available(8) {
+            Ok(u64::from(self.reader.read_u32_be()?) << 32 | u64::from(self.reader.read_u32_be()?))
+        } else {
+            Ok(default)
+        }
+    }
+
+    fn bytes(&mut self) -> io::Result<[u8; 32]> {
+        let mut buf = [0; 32];
+        if self.available(32) {
+            for b in buf.iter_mut() {
+                *b = self.reader.read_u8()?;
+            }
+        }
+        Ok(buf)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::MobiHeader;
-    use crate::{book, Reader, TextEncoding};
+    use super::{offsets, MobiHeader, MobiType, DRM_ON_FLAG, NULL_INDEX};
+    use crate::reader::{put_u16, put_u32};
+    use crate::{book, reader::MobiReader, Reader, TextEncoding};
 
     #[test]
+    #[allow(deprecated)]
     fn parse() {
         let mobiheader = MobiHeader {
             identifier: 1297039945,
@@ -247,6 +495,16 @@ mod tests {
             text_encoding: 65001,
             id: 3428045761,
             gen_version: 6,
+            orthographic_index: NULL_INDEX,
+            inflection_index: NULL_INDEX,
+            index_names: NULL_INDEX,
+            index_keys: NULL_INDEX,
+            extra_index0: NULL_INDEX,
+            extra_index1: NULL_INDEX,
+            extra_index2: NULL_INDEX,
+            extra_index3: NULL_INDEX,
+            extra_index4: NULL_INDEX,
+            extra_index5: NULL_INDEX,
             first_non_book_index: 284,
             // name: String::from("Lord of the Rings - Fellowship of the Ring"),
             name: String::new(),
@@ -262,22 +520,208 @@ mod tests {
             first_data_record: 0,
             data_record_count: 0,
             exth_flags: 80,
+            unknown0: [0; 32],
+            unknown1: 4294967295,
             drm_offset: 4294967295,
             drm_count: 0,
             drm_size: 0,
             drm_flags: 0,
+            unknown2: 0,
+            first_content_record: 1,
+            last_content_record: 288,
             last_image_record: 288,
+            fdst_index: NULL_INDEX,
+            fdst_count: 0,
             fcis_record: 290,
+            fcis_count: 1,
             flis_record: 289,
+            flis_count: 1,
+            unknown3: 0,
+            srcs_index: NULL_INDEX,
+            srcs_count: 0,
+            unknown4: 4294967295,
+            unknown5: 4294967295,
+            unknown6: 0,
+            extra_data_flags: 7,
+            ncx_index: 284,
+            fragment_index: NULL_INDEX,
+            skeleton_index: NULL_INDEX,
+            datp_index: NULL_INDEX,
+            guide_index: NULL_INDEX,
+            unknown7: 0,
+            unknown8: 0,
+            unknown9: 0,
+            unknown10: 0,
         };
 
-        let mut reader = Reader::new(&book::MOBIHEADER);
+        let book = book::full_book();
+        let mut reader = Reader::new(&book[book::MOBIHEADER_OFFSET..]);
         let test_header = MobiHeader::partial_parse(&mut reader).unwrap();
         // test_header.finish_parse(&mut reader).expect("Should find name");
 
         assert_eq!(mobiheader, test_header);
+        assert_eq!(reader.get_position(), 232);
+    }
+
+    #[test]
+    fn parse_short_header() {
+        let mut content = book::MOBIHEADER[..0x74].to_vec();
+        content[4..8].copy_from_slice(&0x74u32.to_be_bytes());
+
+        let mut reader = Reader::new(&content[..]);
+        let header = MobiHeader::partial_parse(&mut reader).unwrap();
+
+        assert_eq!(header.header_length, 0x74);
+        assert_eq!(header.exth_flags, 80);
+        assert_eq!(header.drm_offset, DRM_ON_FLAG);
+        assert_eq!(header.fcis_record, NULL_INDEX);
+        assert_eq!(header.flis_record, NULL_INDEX);
+        assert_eq!(header.extra_data_flags, 0);
+        assert_eq!(reader.get_position(), 0x74);
+    }
+
+    #[test]
+    fn parse_unaligned_header() {
+        // Header ends in the middle of the 8 byte field preceding the first
+        // content record, whose bytes must not be read as one
+        let mut content = book::MOBIHEADER[..0xAE].to_vec();
+        content[4..8].copy_from_slice(&0xAEu32.to_be_bytes());
+        content[0xA8..0xAA].copy_from_slice(&[0xFF, 0xFF]);
+
+        let mut reader = Reader::new(&content[..]);
+        let header = MobiHeader::partial_parse(&mut reader).unwrap();
+
+        assert_eq!(header.header_length, 0xAE);
+        assert_eq!(header.drm_offset, 4294967295);
+        assert_eq!(header.unknown2, 0);
+        assert_eq!(header.first_content_record, 0);
+        assert_eq!(header.last_content_record, 0);
+        assert_eq!(header.fcis_record, NULL_INDEX);
+        assert_eq!(reader.get_position(), 0xAE);
+    }
+
+    #[test]
+    fn parses_fdst_only_in_kf8_headers() {
+        // FDST record and count, the count being an unknown 1 before KF8
+        let fields = vec![(0xC0, 5), (0xC4, 1)];
+        let parse = |record0: book::Record0| {
+            let record0 = record0.build();
+            MobiHeader::partial_parse(&mut Reader::new(&record0[16..])).unwrap()
+        };
+
+        let header = parse(book::Record0 {
+            fields: fields.clone(),
+            ..Default::default()
+        });
+        assert_eq!((header.fdst_index, header.fdst_count), (NULL_INDEX, 0));
+
+        let header = parse(book::Record0 {
+            header_length: 264,
+            format_version: 8,
+            fields,
+            ..Default::default()
+        });
+        assert_eq!((header.fdst_index, header.fdst_count), (5, 1));
+    }
+
+    #[test]
+    fn offsets_match_parsed_fields() {
+        let mut content = vec![0; 0x108];
+        put_u32(&mut content, offsets::HEADER_LENGTH, 0x108);
+        let fields = [
+            offsets::ORTHOGRAPHIC_INDEX,
+            offsets::INFLECTION_INDEX,
+            offsets::INDEX_NAMES,
+            offsets::INDEX_KEYS,
+            offsets::EXTRA_INDEX0,
+            offsets::EXTRA_INDEX5,
+            offsets::FIRST_NON_BOOK_INDEX,
+            offsets::NAME_OFFSET,
+            offsets::NAME_LENGTH,
+            offsets::FIRST_IMAGE_INDEX,
+            offsets::FIRST_HUFF_RECORD,
+            offsets::HUFF_RECORD_COUNT,
+            offsets::FIRST_DATA_RECORD,
+            offsets::DATA_RECORD_COUNT,
+            offsets::EXTH_FLAGS,
+            offsets::DRM_OFFSET,
+            offsets::DRM_COUNT,
+            offsets::DRM_FLAGS,
+            offsets::FDST_COUNT,
+            offsets::FCIS_RECORD,
+            offsets::FCIS_COUNT,
+            offsets::FLIS_RECORD,
+            offsets::FLIS_COUNT,
+            offsets::SRCS_INDEX,
+            offsets::SRCS_COUNT,
+            offsets::NCX_INDEX,
+            offsets::DATP_INDEX,
+        ];
+        for &offset in &fields {
+            put_u32(&mut content, offset, offset as u32);
+        }
+        put_u16(
+            &mut content,
+            offsets::EXTRA_DATA_FLAGS,
+            offsets::EXTRA_DATA_FLAGS as u16,
+        );
+
+        // Version older than KF8 splits the FDST index into content records
+        put_u32(&mut content, offsets::FORMAT_VERSION, 6);
+        put_u16(&mut content, offsets::FIRST_CONTENT_RECORD, 1);
+        put_u16(&mut content, offsets::LAST_CONTENT_RECORD, 2);
+        let h = MobiHeader::partial_parse(&mut Reader::new(&content[..])).unwrap();
+        let parsed = [
+            h.orthographic_index,
+            h.inflection_index,
+            h.index_names,
+            h.index_keys,
+            h.extra_index0,
+            h.extra_index5,
+            h.first_non_book_index,
+            h.name_offset,
+            h.name_length,
+            h.first_image_index,
+            h.first_huff_record,
+            h.huff_record_count,
+            h.first_data_record,
+            h.data_record_count,
+            h.exth_flags,
+            h.drm_offset,
+            h.drm_count,
+            h.drm_flags,
+            h.fdst_count,
+            h.fcis_record,
+            h.fcis_count,
+            h.flis_record,
+            h.flis_count,
+            h.srcs_index,
+            h.srcs_count,
+            h.ncx_index,
+            h.datp_index,
+        ];
+        let expected: Vec<_> = fields.iter().map(|&offset| offset as u32).collect();
+        assert_eq!(parsed.to_vec(), expected);
+        assert_eq!(h.extra_data_flags as usize, offsets::EXTRA_DATA_FLAGS);
+        assert_eq!((h.first_content_record, h.last_content_record), (1, 2));
+
+        for &offset in &[
+            offsets::FDST_INDEX,
+            offsets::FRAGMENT_INDEX,
+            offsets::SKELETON_INDEX,
+            offsets::GUIDE_INDEX,
+        ] {
+            put_u32(&mut content, offset, offset as u32);
+        }
+        put_u32(&mut content, offsets::FORMAT_VERSION, 8);
+        let h = MobiHeader::partial_parse(&mut Reader::new(&content[..])).unwrap();
+        assert_eq!(
+            [h.fdst_index, h.fragment_index, h.skeleton_index, h.guide_index],
+            [176, 232, 236, 244]
+        );
     }
 
+    #[allow(clippy::field_reassign_with_default)]
     mod text_encoding {
         use super::*;
         #[test]
@@ -297,24 +741,26 @@ mod tests {
     #[test]
     fn parses_mobi_types() {
         macro_rules! mtype {
-            ($mt: expr, $s: expr) => {
+            ($mt: expr, $t: expr, $s: expr) => {
                 let mut m = MobiHeader::default();
                 m.mobi_type = $mt;
-                assert_eq!(m.mobi_type(), Some(String::from($s)))
+                assert_eq!(m.mobi_type(), $t);
+                assert_eq!(m.mobi_type().to_string(), String::from($s))
             };
         }
-        mtype!(2, "Mobipocket Book");
-        mtype!(3, "PalmDoc Book");
-        mtype!(4, "Audio");
-        mtype!(257, "News");
-        mtype!(258, "News Feed");
-        mtype!(259, "News Magazine");
-        mtype!(513, "PICS");
-        mtype!(514, "WORD");
-        mtype!(515, "XLS");
-        mtype!(516, "PPT");
-        mtype!(517, "TEXT");
-        mtype!(518, "HTML");
+        mtype!(2, MobiType::MobipocketBook, "Mobipocket Book");
+        mtype!(3, MobiType::PalmDocBook, "PalmDoc Book");
+        mtype!(4, MobiType::Audio, "Audio");
+        mtype!(257, MobiType::News, "News");
+        mtype!(258, MobiType::NewsFeed, "News Feed");
+        mtype!(259, MobiType::NewsMagazine, "News Magazine");
+        mtype!(513, MobiType::PICS, "PICS");
+        mtype!(514, MobiType::WORD, "WORD");
+        mtype!(515, MobiType::XLS, "XLS");
+        mtype!(516, MobiType::PPT, "PPT");
+        mtype!(517, MobiType::TEXT, "TEXT");
+        mtype!(518, MobiType::HTML, "HTML");
+        mtype!(1, MobiType::Unknown(1), "Unknown (1)");
     }
 
     #[test]
@@ -4,22 +4,27 @@ pub(crate) mod mobih;
 pub(crate) mod palmdoch;
 pub(crate) mod records;
 
+pub(crate) use self::header::PDB_HEADER_LEN;
 pub use self::{
     exth::{ExtHeader, ExthRecord},
     header::Header,
-    mobih::{MobiHeader, TextEncoding},
-    palmdoch::PalmDocHeader,
+    mobih::{MobiHeader, MobiType, TextEncoding, NULL_INDEX},
+    palmdoch::{Compression, Encryption, PalmDocHeader},
 };
 
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, HeaderKind};
 use crate::headers::records::Records;
-use crate::reader::{MobiReader, ReaderPrime};
-use crate::Reader;
+use crate::reader::{MobiReader, Reader};
+use crate::source::{Source, StreamSource};
+use crate::{ParseOptions, RecordKind};
 #[cfg(feature = "time")]
 use chrono::NaiveDateTime;
 use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::io::{self, Read};
 use std::path::Path;
 
+const BOUNDARY_MAGIC: &str = "BOUNDARY";
+
 #[derive(Debug, Default)]
 /// Holds all headers containing low level metadata of a mobi book
 pub struct MobiMetadata {
@@ -28,53 +33,214 @@ pub struct MobiMetadata {
     pub palmdoc: PalmDocHeader,
     pub mobi: MobiHeader,
     pub exth: ExtHeader,
+    /// Headers of the KF8 part of a joint MOBI/KF8 file, if there is one
+    pub kf8: Option<Kf8Metadata>,
+    /// Anomalies found in headers while parsing them leniently
+    pub diagnostics: Diagnostics,
 }
+
+#[derive(Debug, Default)]
+/// Holds the second set of headers found in joint MOBI/KF8 files, right after
+/// the KF8 boundary record. Record indexes found in those headers are relative
+/// to `record0`.
+pub struct Kf8Metadata {
+    /// Index of the record containing KF8 headers
+    pub record0: u32,
+    pub palmdoc: PalmDocHeader,
+    pub mobi: MobiHeader,
+    pub exth: ExtHeader,
+}
+
 impl MobiMetadata {
     /// Construct a Metadata object from a slice of bytes
     pub fn new<B: AsRef<Vec<u8>>>(bytes: B) -> io::Result<MobiMetadata> {
-        MobiMetadata::from_reader(&mut ReaderPrime::new(std::io::Cursor::new(bytes.as_ref())))
+        MobiMetadata::new_with_options(bytes, &ParseOptions::default())
+    }
+
+    /// Construct a Metadata object from a slice of bytes, parsed with passed options
+    pub fn new_with_options<B: AsRef<Vec<u8>>>(bytes: B, options: &ParseOptions) -> io::Result<MobiMetadata> {
+        MobiMetadata::from_reader(&mut Reader::new(&bytes.as_ref()[..]), options)
     }
 
-    /// Construct a Metadata object from passed file path
+    /// Construct a Metadata object from passed file path. Only parts of the file
+    /// containing headers are read.
     pub fn from_path<P: AsRef<Path>>(file_path: P) -> io::Result<MobiMetadata> {
-        let mut reader = ReaderPrime::new(BufReader::new(File::open(file_path)?));
-        MobiMetadata::from_reader(&mut reader)
+        MobiMetadata::from_path_with_options(file_path, &ParseOptions::default())
+    }
+
+    /// Construct a Metadata object from passed file path, parsed with passed options
+    pub fn from_path_with_options<P: AsRef<Path>>(file_path: P, options: &ParseOptions) -> io::Result<MobiMetadata> {
+        MobiMetadata::from_source_with_options(File::open(file_path)?, options)
     }
 
-    /// Construct a Metadata object from an object that implements a Read trait
+    /// Construct a Metadata object from an object that implements a Read trait.
+    /// The reader is consumed only up to the end of headers, so truncated files
+    /// can be read as well. In joint MOBI/KF8 files it is read up to the end of
+    /// KF8 headers, unless it ends earlier.
     pub fn from_read<R: Read>(reader: R) -> io::Result<MobiMetadata> {
-        let content: Vec<_> = reader.bytes().flatten().collect();
-        MobiMetadata::from_reader(&mut Reader::new(&content))
+        MobiMetadata::from_read_with_options(reader, &ParseOptions::default())
+    }
+
+    /// Construct a Metadata object from an object that implements a Read trait,
+    /// parsed with passed options
+    pub fn from_read_with_options<R: Read>(reader: R, options: &ParseOptions) -> io::Result<MobiMetadata> {
+        MobiMetadata::from_reader(&mut Reader::exact(StreamSource::new(reader)), options)
+    }
+
+    /// Construct a Metadata object from any source of content. Only parts of
+    /// the content containing headers are read.
+    pub fn from_source<S: Source>(source: S) -> io::Result<MobiMetadata> {
+        MobiMetadata::from_source_with_options(source, &ParseOptions::default())
+    }
+
+    /// Construct a Metadata object from any source of content, parsed with passed options
+    pub fn from_source_with_options<S: Source>(source: S, options: &ParseOptions) -> io::Result<MobiMetadata> {
+        MobiMetadata::from_reader(&mut Reader::new(source), options)
     }
 
-    pub(crate) fn from_reader(reader: &mut impl MobiReader) -> io::Result<MobiMetadata> {
+    pub(crate) fn from_reader(reader: &mut impl MobiReader, options: &ParseOptions) -> io::Result<MobiMetadata> {
         let header = Header::parse(reader)?;
+        options.check_record_count(usize::from(header.num_records))?;
         reader.set_num_records(header.num_records);
         let records = Records::parse(reader)?;
+        let mut diagnostics = Diagnostics::new();
+        let unordered = records.records.windows(2).position(|w| w[0].0 > w[1].0);
+        options.check(&mut diagnostics, unordered.is_none(), || {
+            let index = unordered.unwrap_or_default() + 1;
+            Diagnostic::new(
+                DiagnosticKind::UnorderedRecords,
+                "record offsets are not in increasing order",
+            )
+            .header(HeaderKind::Records)
+            .record(index)
+            .offset(u64::from(records.records[index].0))
+        })?;
+
+        let (palmdoc, mobi, exth) = MobiMetadata::parse_record0(reader, &records, 0, options, &mut diagnostics)?;
+
+        let kf8 = match exth.kf8_boundary() {
+            // Truncated files can still be read, only without KF8 headers
+            Some(kf8_record0) => {
+                match MobiMetadata::parse_kf8(reader, &records, kf8_record0, options, &mut diagnostics) {
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof && !options.is_strict() => {
+                        diagnostics.push(
+                            Diagnostic::new(DiagnosticKind::TruncatedKf8, "KF8 headers end past the end of the file")
+                                .header(HeaderKind::Mobi)
+                                .record(kf8_record0 as usize),
+                        );
+                        None
+                    }
+                    kf8 => kf8?,
+                }
+            }
+            None => None,
+        };
+
+        Ok(MobiMetadata {
+            header,
+            records,
+            palmdoc,
+            mobi,
+            exth,
+            kf8,
+            diagnostics,
+        })
+    }
+
+    /// Parses KF8 headers located in record `kf8_record0`, following the boundary record
+    fn parse_kf8(
+        reader: &mut impl MobiReader,
+        records: &Records,
+        kf8_record0: u32,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Option<Kf8Metadata>> {
+        if kf8_record0 == 0 || kf8_record0 == NULL_INDEX {
+            return Ok(None);
+        }
+        if kf8_record0 as usize >= records.records.len() {
+            options.check(diagnostics, false, || {
+                Diagnostic::new(
+                    DiagnosticKind::InvalidKf8Boundary,
+                    format!("KF8 record 0 at {} is past the end of the record list", kf8_record0),
+                )
+                .header(HeaderKind::Exth)
+            })?;
+            return Ok(None);
+        }
+
+        let boundary = records.offset(kf8_record0 as usize - 1)?;
+        if reader.read_string_header(boundary, BOUNDARY_MAGIC.len())? != BOUNDARY_MAGIC {
+            return Ok(None);
+        }
+
+        let (palmdoc, mobi, exth) =
+            MobiMetadata::parse_record0(reader, records, kf8_record0 as usize, options, diagnostics)?;
+        Ok(Some(Kf8Metadata {
+            record0: kf8_record0,
+            palmdoc,
+            mobi,
+            exth,
+        }))
+    }
+
+    /// Parses the set of headers contained in the record at `index`, being
+    /// record 0 of either MOBI or KF8 part of the file
+    fn parse_record0(
+        reader: &mut impl MobiReader,
+        records: &Records,
+        index: usize,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<(PalmDocHeader, MobiHeader, ExtHeader)> {
+        let offset = records.offset(index)?;
+        reader.set_position(offset);
         let palmdoc = PalmDocHeader::parse(reader)?;
         let mut mobi = MobiHeader::partial_parse(reader)?;
+        options.check(diagnostics, mobi.has_known_encoding(), || {
+            Diagnostic::new(
+                DiagnosticKind::UnknownTextEncoding,
+                format!("unknown text encoding {}", mobi.text_encoding),
+            )
+            .header(HeaderKind::Mobi)
+            .record(index)
+            .offset(offset + 28)
+        })?;
 
         let exth = {
-            if mobi.has_exth_header() {
-                ExtHeader::parse(reader, mobi.header_length)?
+            if mobi.has_exth_header() && !options.skip_exth {
+                let exth_offset = offset + 16 + u64::from(mobi.header_length);
+                ExtHeader::parse(reader, exth_offset, options, diagnostics)?
             } else {
                 ExtHeader::default()
             }
         };
 
-        let offset1 = reader.position_after_records() + 80 + mobi.name_offset as u64;
-        let offset = records.records[0].0 + mobi.name_offset;
+        mobi.finish_parse(reader, offset)?;
+        Ok((palmdoc, mobi, exth))
+    }
 
-        assert_eq!(offset as u64, offset1);
-        mobi.finish_parse(reader)?;
+    /// Returns headers describing the text record at `index`, which in joint
+    /// MOBI/KF8 files depend on the part the record belongs to.
+    pub(crate) fn text_headers(&self, index: usize) -> (&PalmDocHeader, &MobiHeader) {
+        match &self.kf8 {
+            Some(kf8) if index > kf8.record0 as usize => (&kf8.palmdoc, &kf8.mobi),
+            _ => (&self.palmdoc, &self.mobi),
+        }
+    }
 
-        Ok(MobiMetadata {
-            header,
-            records,
-            palmdoc,
-            mobi,
-            exth,
-        })
+    /// Returns indexes of records holding the text of the book. In joint MOBI/KF8
+    /// files only text records of the MOBI part are returned.
+    pub(crate) fn text_record_indexes(&self, kinds: &[RecordKind]) -> Vec<usize> {
+        let end = self.kf8.as_ref().map(|kf8| kf8.record0 as usize).unwrap_or(usize::MAX);
+
+        kinds
+            .iter()
+            .enumerate()
+            .take_while(|(i, _)| *i < end)
+            .filter(|(_, kind)| **kind == RecordKind::Text)
+            .map(|(i, _)| i)
+            .collect()
     }
 
     //################################################################################//
@@ -143,7 +309,7 @@ impl MobiMetadata {
     }
 
     /// Returns type of this ebook
-    pub fn mobi_type(&self) -> Option<String> {
+    pub fn mobi_type(&self) -> MobiType {
         self.mobi.mobi_type()
     }
 
@@ -179,12 +345,12 @@ impl MobiMetadata {
     }
 
     /// Returns compression method used on this file
-    pub fn compression(&self) -> String {
+    pub fn compression(&self) -> Compression {
         self.palmdoc.compression()
     }
 
     /// Returns encryption method used on this file
-    pub fn encryption(&self) -> String {
+    pub fn encryption(&self) -> Encryption {
         self.palmdoc.encryption()
     }
 }
@@ -192,12 +358,146 @@ impl MobiMetadata {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::book;
+    use crate::{book, Reader};
 
     #[test]
     fn test_mobi_metadata() {
         let book = book::full_book();
-        let mut reader = Reader::new(&book);
-        assert!(MobiMetadata::from_reader(&mut reader).is_ok());
+        let mut reader = Reader::new(&book[..]);
+        let metadata = MobiMetadata::from_reader(&mut reader, &ParseOptions::default()).unwrap();
+        assert_eq!(metadata.mobi.name, "Lord of the Rings - Fellowship of the Ring");
+        assert!(metadata.kf8.is_none());
+    }
+
+    #[test]
+    fn parses_with_gap_before_record0() {
+        let record0 = book::Record0 {
+            exth: vec![(100, b"Author".to_vec())],
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), b"text".to_vec()], 37);
+        let metadata = MobiMetadata::from_reader(&mut Reader::new(&book[..]), &ParseOptions::default()).unwrap();
+
+        assert_eq!(metadata.mobi.name, "Test Book");
+        assert_eq!(metadata.author(), Some(String::from("Author")));
+    }
+
+    #[test]
+    fn parses_kf8_headers() {
+        let mobi6 = book::Record0 {
+            exth: vec![(121, 3u32.to_be_bytes().to_vec())],
+            name: "Old",
+            ..Default::default()
+        };
+        let kf8 = book::Record0 {
+            header_length: 264,
+            format_version: 8,
+            exth: vec![(100, b"Author".to_vec())],
+            name: "New",
+            ..Default::default()
+        };
+        let records = [mobi6.build(), b"text".to_vec(), b"BOUNDARY".to_vec(), kf8.build()];
+        let book = book::pdb(&records, 2);
+        let metadata = MobiMetadata::from_reader(&mut Reader::new(&book[..]), &ParseOptions::default()).unwrap();
+
+        assert_eq!(metadata.mobi.name, "Old");
+        let kf8 = metadata.kf8.unwrap();
+        assert_eq!(kf8.record0, 3);
+        assert_eq!(kf8.mobi.name, "New");
+        assert!(kf8.mobi.is_kf8());
+        assert_eq!(
+            kf8.exth.get_record_string_lossy(ExthRecord::Author),
+            Some(String::from("Author"))
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_kf8_boundary() {
+        for &kf8_record0 in &[NULL_INDEX, 2, 5] {
+            let mobi6 = book::Record0 {
+                exth: vec![(121, kf8_record0.to_be_bytes().to_vec())],
+                ..Default::default()
+            };
+            let book = book::pdb(&[mobi6.build(), b"text".to_vec()], 2);
+
+            let metadata = MobiMetadata::new(&book).unwrap();
+            assert!(metadata.kf8.is_none());
+            assert_eq!(
+                metadata.diagnostics.contains(DiagnosticKind::InvalidKf8Boundary),
+                kf8_record0 != NULL_INDEX
+            );
+            assert_eq!(
+                MobiMetadata::new_with_options(&book, &ParseOptions::new().strict()).is_ok(),
+                kf8_record0 == NULL_INDEX
+            );
+        }
+    }
+
+    #[test]
+    fn reads_only_headers() {
+        struct Counting<'a>(&'a [u8], usize);
+        impl Read for Counting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let n = (&self.0[self.1..]).read(buf)?;
+                self.1 += n;
+                Ok(n)
+            }
+        }
+
+        let book = book::full_book();
+        let mut reader = Counting(&book, 0);
+        let metadata = MobiMetadata::from_read(&mut reader).unwrap();
+        assert_eq!(
+            metadata.title(),
+            Some(String::from("Lord of the Rings - Fellowship of the Ring"))
+        );
+
+        let name_end = metadata.records.records[0].0 + metadata.mobi.name_offset + metadata.mobi.name_length;
+        assert_eq!(reader.1, name_end as usize);
+    }
+
+    #[test]
+    fn reads_truncated_file() {
+        let mobi6 = book::Record0 {
+            exth: vec![(121, 3u32.to_be_bytes().to_vec()), (100, b"Author".to_vec())],
+            ..Default::default()
+        };
+        let records = [
+            mobi6.build(),
+            vec![0; 4096],
+            b"BOUNDARY".to_vec(),
+            book::Record0::default().build(),
+        ];
+        let book = book::pdb(&records, 2);
+        let truncated = &book[..book.len() - 3000];
+
+        let metadata = MobiMetadata::from_read(truncated).unwrap();
+        assert_eq!(metadata.author(), Some(String::from("Author")));
+        assert!(metadata.kf8.is_none());
+        assert!(metadata.diagnostics.contains(DiagnosticKind::TruncatedKf8));
+        assert!(MobiMetadata::from_read(&book[..]).unwrap().kf8.is_some());
+        assert!(MobiMetadata::from_read_with_options(truncated, &ParseOptions::new().strict()).is_err());
+    }
+
+    #[test]
+    fn parses_with_options() {
+        let record0 = book::Record0 {
+            exth: vec![(100, b"Author".to_vec())],
+            text_encoding: 1250,
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), b"text".to_vec()], 2);
+
+        let metadata = MobiMetadata::new(&book).unwrap();
+        assert_eq!(metadata.text_encoding(), TextEncoding::CP1252);
+        let diagnostic = metadata.diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.kind, DiagnosticKind::UnknownTextEncoding);
+        assert_eq!(diagnostic.header, Some(HeaderKind::Mobi));
+        assert_eq!(diagnostic.record, Some(0));
+        assert!(MobiMetadata::new_with_options(&book, &ParseOptions::new().strict()).is_err());
+
+        let metadata = MobiMetadata::new_with_options(&book, &ParseOptions::new().skip_exth(true)).unwrap();
+        assert_eq!(metadata.author(), None);
+        assert_eq!(metadata.mobi.name, "Test Book");
     }
 }
@@ -1,52 +1,71 @@
 use crate::reader::MobiReader;
-use std::io;
+use std::{fmt, io};
+
+/// Length of the PalmDOC header, which MOBI header follows in the header record
+pub(crate) const PALMDOC_HEADER_LEN: usize = 16;
+
+/// Offsets of PalmDOC header fields from the beginning of the header record
+pub(crate) mod offsets {
+    pub(crate) const COMPRESSION: usize = 0;
+    pub(crate) const TEXT_LENGTH: usize = 4;
+    pub(crate) const RECORD_COUNT: usize = 8;
+    pub(crate) const RECORD_SIZE: usize = 10;
+}
 
 /// Compression types available in MOBI format.
-pub(crate) enum Compression {
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Compression {
     No,
     PalmDoc,
     Huff,
+    Unknown(u16),
 }
 impl From<u16> for Compression {
     fn from(n: u16) -> Compression {
         match n {
+            1 => Compression::No,
             2 => Compression::PalmDoc,
             17480 => Compression::Huff,
-            _ => Compression::No,
+            n => Compression::Unknown(n),
         }
     }
 }
-impl ToString for Compression {
-    fn to_string(&self) -> String {
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Compression::No => String::from("No Compression"),
-            Compression::PalmDoc => String::from("PalmDOC Compression"),
-            Compression::Huff => String::from("HUFF/CFIC Compression"),
+            Compression::No => write!(f, "No Compression"),
+            Compression::PalmDoc => write!(f, "PalmDOC Compression"),
+            Compression::Huff => write!(f, "HUFF/CFIC Compression"),
+            Compression::Unknown(n) => write!(f, "Unknown Compression ({})", n),
         }
     }
 }
 
 /// Encryption types available in MOBI format.
-pub(crate) enum Encryption {
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encryption {
     No,
     OldMobipocket,
     Mobipocket,
+    Unknown(u16),
 }
 impl From<u16> for Encryption {
     fn from(n: u16) -> Encryption {
         match n {
-            2 => Encryption::Mobipocket,
+            0 => Encryption::No,
             1 => Encryption::OldMobipocket,
-            _ => Encryption::No,
+            2 => Encryption::Mobipocket,
+            n => Encryption::Unknown(n),
         }
     }
 }
-impl ToString for Encryption {
-    fn to_string(&self) -> String {
+impl fmt::Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Encryption::No => String::from("No Encryption"),
-            Encryption::OldMobipocket => String::from("Old Mobipocket Encryption"),
-            Encryption::Mobipocket => String::from("Mobipocket Encryption"),
+            Encryption::No => write!(f, "No Encryption"),
+            Encryption::OldMobipocket => write!(f, "Old Mobipocket Encryption"),
+            Encryption::Mobipocket => write!(f, "Mobipocket Encryption"),
+            Encryption::Unknown(n) => write!(f, "Unknown Encryption ({})", n),
         }
     }
 }
@@ -81,16 +100,12 @@ impl PalmDocHeader {
         })
     }
 
-    pub(crate) fn compression(&self) -> String {
-        Compression::from(self.compression).to_string()
-    }
-
-    pub(crate) fn encryption(&self) -> String {
-        Encryption::from(self.encryption_type).to_string()
+    pub(crate) fn compression(&self) -> Compression {
+        Compression::from(self.compression)
     }
 
-    pub(crate) fn compression_enum(&self) -> Compression {
-        Compression::from(self.compression)
+    pub(crate) fn encryption(&self) -> Encryption {
+        Encryption::from(self.encryption_type)
     }
 }
 
@@ -109,7 +124,7 @@ mod tests {
             encryption_type: 0,
         };
 
-        let mut reader = Reader::new(&book::PALMDOCHEADER);
+        let mut reader = Reader::new(&book::PALMDOCHEADER[..]);
 
         assert_eq!(pdheader, PalmDocHeader::parse(&mut reader).unwrap());
     }
@@ -117,46 +132,56 @@ mod tests {
     mod compression_type {
         use super::*;
         macro_rules! compression {
-            ($et: expr, $s: expr) => {
+            ($et: expr, $c: expr, $s: expr) => {
                 let mut pdheader = PalmDocHeader::default();
                 pdheader.compression = $et;
-                assert_eq!(pdheader.compression(), String::from($s))
+                assert_eq!(pdheader.compression(), $c);
+                assert_eq!(pdheader.compression().to_string(), String::from($s))
             };
         }
         #[test]
         fn no_compression() {
-            compression!(1, "No Compression");
+            compression!(1, Compression::No, "No Compression");
         }
         #[test]
         fn palmdoc_compression() {
-            compression!(2, "PalmDOC Compression");
+            compression!(2, Compression::PalmDoc, "PalmDOC Compression");
         }
         #[test]
         fn huff_compression() {
-            compression!(17480, "HUFF/CFIC Compression");
+            compression!(17480, Compression::Huff, "HUFF/CFIC Compression");
+        }
+        #[test]
+        fn unknown_compression() {
+            compression!(3, Compression::Unknown(3), "Unknown Compression (3)");
         }
     }
 
     mod encryption_type {
         use super::*;
         macro_rules! encryption {
-            ($et: expr, $s: expr) => {
+            ($et: expr, $e: expr, $s: expr) => {
                 let mut pdheader = PalmDocHeader::default();
                 pdheader.encryption_type = $et;
-                assert_eq!(pdheader.encryption(), String::from($s))
+                assert_eq!(pdheader.encryption(), $e);
+                assert_eq!(pdheader.encryption().to_string(), String::from($s))
             };
         }
         #[test]
         fn no_encryption() {
-            encryption!(0, "No Encryption");
+            encryption!(0, Encryption::No, "No Encryption");
         }
         #[test]
         fn old_mobipocket_encryption() {
-            encryption!(1, "Old Mobipocket Encryption");
+            encryption!(1, Encryption::OldMobipocket, "Old Mobipocket Encryption");
         }
         #[test]
         fn mobipocket_encryption() {
-            encryption!(2, "Mobipocket Encryption");
+            encryption!(2, Encryption::Mobipocket, "Mobipocket Encryption");
+        }
+        #[test]
+        fn unknown_encryption() {
+            encryption!(7, Encryption::Unknown(7), "Unknown Encryption (7)");
         }
     }
 }
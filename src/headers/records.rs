@@ -12,6 +12,9 @@ const EXTRA_BYTES_FLAG: u16 = 0xFFFE;
 #[derive(Debug, PartialEq, Default)]
 pub struct Records {
     pub records: Vec<(u32, u32)>,
+    /// Count of bits set in the padding following the record list, doubled.
+    /// It doesn't describe the records, whose bounds come from their offsets.
+    #[deprecated(note = "record bounds are derived from record offsets")]
     pub extra_bytes: u32,
 }
 
@@ -22,13 +25,36 @@ impl Records {
         for _ in 0..reader.get_num_records() {
             records.push((reader.read_u32_be()?, reader.read_u32_be()?));
         }
+        // Two bytes of padding separating the record list from record data
         let extra_bytes = reader.read_u16_be()?;
 
+        #[allow(deprecated)]
         Ok(Records {
             records,
             extra_bytes: 2 * (extra_bytes & EXTRA_BYTES_FLAG).count_ones(),
         })
     }
+
+    /// Returns start and end offsets of the record at `index`, with `len` being
+    /// the length of the whole file. Offsets are clamped to the file length.
+    pub(crate) fn bounds(&self, index: usize, len: u64) -> Option<(u64, u64)> {
+        let start = u64::from(self.records.get(index)?.0);
+        let end = self
+            .records
+            .get(index + 1)
+            .map(|&(offset, _)| u64::from(offset))
+            .unwrap_or(len)
+            .min(len);
+        Some((start.min(end), end))
+    }
+
+    /// Returns the offset of record at `index` from the beginning of the file
+    pub(crate) fn offset(&self, index: usize) -> io::Result<u64> {
+        self.records
+            .get(index)
+            .map(|&(offset, _)| u64::from(offset))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("record {} does not exist", index)))
+    }
 }
 
 #[cfg(test)]
@@ -38,7 +64,7 @@ mod test {
 
     #[test]
     fn parse() {
-        let mut reader = Reader::new(&book::RECORDS);
+        let mut reader = Reader::new(&book::RECORDS[..]);
         reader.set_num_records(292);
 
         assert!(Records::parse(&mut reader).is_ok());
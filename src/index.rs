@@ -0,0 +1,494 @@
+use crate::headers::TextEncoding;
+use crate::reader::{MobiReader, Reader};
+use crate::text::TextDecoder;
+use crate::Mobi;
+use std::io;
+
+const INDX_MAGIC: &[u8] = b"INDX";
+const TAGX_MAGIC: &[u8] = b"TAGX";
+const IDXT_MAGIC: &[u8] = b"IDXT";
+const ORDT_MAGIC: &[u8] = b"ORDT";
+/// Headers of INDX records holding ORDT offsets are at least this long
+const ORDT_HEADER_LEN: u32 = 180;
+
+/// Definition of a tag from the TAGX section, telling how its values are
+/// stored after the control bytes of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TagDefinition {
+    tag: u8,
+    values_per_entry: u8,
+    mask: u8,
+    /// Marks the end of tags described by the current control byte
+    end: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Entry of an index, holding its label and values of its tags
+pub(crate) struct IndexEntry {
+    /// Label as stored in the record, either in the encoding of the index or
+    /// as codes of the ORDT table
+    pub label: Vec<u8>,
+    pub tags: Vec<(u8, Vec<u32>)>,
+}
+
+impl IndexEntry {
+    /// Returns values of `tag`, or `None` if the entry doesn't have it
+    pub(crate) fn tag(&self, tag: u8) -> Option<&[u32]> {
+        self.tags.iter().find(|(t, _)| *t == tag).map(|(_, values)| &values[..])
+    }
+}
+
+/// ORDT table of an index, mapping codes of labels to UTF-16 characters. The
+/// other ORDT table only sets the collation order of codes, which lookups of
+/// whole labels don't depend on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Ordt {
+    /// Codes take two bytes instead of one
+    wide: bool,
+    chars: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Index of a book spread over a header INDX record, INDX records with its
+/// entries and CNCX records with strings referenced by tag values
+pub(crate) struct Index {
+    pub entries: Vec<IndexEntry>,
+    encoding: TextEncoding,
+    ordt: Option<Ordt>,
+    cncx: Vec<Vec<u8>>,
+}
+
+impl Index {
+    /// Parses the index starting with the header INDX record at `record`
+    pub(crate) fn parse(mobi: &Mobi, record: usize) -> io::Result<Index> {
+        let header = index_record(mobi, record)?;
+        let mut reader = Reader::new(header);
+        let header_length = read_u32_at(&mut reader, 4)?;
+        let record_count = read_u32_at(&mut reader, 24)? as usize;
+        let encoding = match read_u32_at(&mut reader, 28)? {
+            65001 => TextEncoding::UTF8,
+            _ => TextEncoding::CP1252,
+        };
+        let cncx_count = read_u32_at(&mut reader, 52)? as usize;
+        let ordt = if header_length >= ORDT_HEADER_LEN {
+            Ordt::parse(&mut reader, header)?
+        } else {
+            None
+        };
+        let (control_bytes, definitions) = parse_tagx(header, header_length as usize)?;
+
+        let mut entries = Vec::new();
+        for i in 0..record_count {
+            parse_entries(
+                index_record(mobi, record + 1 + i)?,
+                control_bytes,
+                &definitions,
+                &mut entries,
+            )?;
+        }
+
+        let mut cncx = Vec::with_capacity(cncx_count.min(mobi.record_count()));
+        for i in 0..cncx_count {
+            let index = record + 1 + record_count + i;
+            let data = mobi.raw_record(index).ok_or_else(|| missing_record(index))?.data;
+            cncx.push(data.to_vec());
+        }
+
+        Ok(Index {
+            entries,
+            encoding,
+            ordt,
+            cncx,
+        })
+    }
+
+    /// Returns characters of a label as UTF-16 code units if the index has an
+    /// ORDT table, or as bytes in the encoding of the index otherwise
+    pub(crate) fn label_units(&self, label: &[u8]) -> Vec<u16> {
+        match &self.ordt {
+            Some(ordt) => ordt.units(label),
+            None => label.iter().map(|&b| u16::from(b)).collect(),
+        }
+    }
+
+    /// Returns the character of a single byte code, such as one of an inflection
+    /// rule, mapped through the ORDT table if the index has one
+    pub(crate) fn code_unit(&self, code: u8) -> u16 {
+        match &self.ordt {
+            Some(ordt) => ordt.unit(u16::from(code)),
+            None => u16::from(code),
+        }
+    }
+
+    /// Decodes a label returned by `Index::label_units`
+    pub(crate) fn decode_units(&self, units: &[u16]) -> String {
+        match &self.ordt {
+            Some(_) => String::from_utf16_lossy(units),
+            None => self.decode(&units.iter().map(|&u| u as u8).collect::<Vec<_>>()),
+        }
+    }
+
+    /// Decodes a label of an entry of this index
+    pub(crate) fn decode_label(&self, label: &[u8]) -> String {
+        self.decode_units(&self.label_units(label))
+    }
+
+    /// Returns the string referenced by `offset` into CNCX records, which is
+    /// prefixed by its length
+    pub(crate) fn cncx_string(&self, offset: u32) -> Option<String> {
+        let record = self.cncx.get((offset >> 16) as usize)?;
+        let pos = (offset & 0xFFFF) as usize;
+        let (len, consumed) = read_var_width(record, pos).ok()?;
+        let start = pos + consumed;
+        let data = record.get(start..start.checked_add(len as usize)?)?;
+        Some(self.decode(data))
+    }
+
+    fn decode(&self, data: &[u8]) -> String {
+        let mut text = String::new();
+        let mut decoder = TextDecoder::new(self.encoding);
+        decoder.decode_lossy(data, &mut text);
+        decoder.finish_lossy(&mut text);
+        text
+    }
+}
+
+impl Ordt {
+    fn parse(reader: &mut Reader<&[u8]>, header: &[u8]) -> io::Result<Option<Ordt>> {
+        let wide = read_u32_at(reader, 164)? != 0;
+        let count = read_u32_at(reader, 168)? as usize;
+        let collation_offset = read_u32_at(reader, 172)? as usize;
+        let chars_offset = read_u32_at(reader, 176)? as usize;
+        if collation_offset == 0 || chars_offset == 0 {
+            return Ok(None);
+        }
+
+        // Both tables are checked, though only characters are needed to decode labels
+        ordt_table(header, collation_offset, count)?;
+        let chars = ordt_table(header, chars_offset, count.saturating_mul(2))?
+            .chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        Ok(Some(Ordt { wide, chars }))
+    }
+
+    fn units(&self, label: &[u8]) -> Vec<u16> {
+        let codes: Vec<u16> = if self.wide {
+            label
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+                .collect()
+        } else {
+            label.iter().map(|&c| u16::from(c)).collect()
+        };
+        codes.into_iter().map(|code| self.unit(code)).collect()
+    }
+
+    fn unit(&self, code: u16) -> u16 {
+        *self.chars.get(usize::from(code)).unwrap_or(&code)
+    }
+}
+
+fn ordt_table(header: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    let table = header
+        .get(offset..)
+        .filter(|table| table.starts_with(ORDT_MAGIC))
+        .ok_or_else(|| invalid("index doesn't have a valid ORDT table"))?;
+    table
+        .get(ORDT_MAGIC.len()..ORDT_MAGIC.len().saturating_add(len))
+        .ok_or_else(|| invalid("ORDT table exceeds the record"))
+}
+
+fn index_record<'a>(mobi: &'a Mobi, index: usize) -> io::Result<&'a [u8]> {
+    let data = mobi.raw_record(index).ok_or_else(|| missing_record(index))?.data;
+    if !data.starts_with(INDX_MAGIC) {
+        return Err(invalid(&format!("record {} is not an INDX record", index)));
+    }
+    Ok(data)
+}
+
+fn read_u32_at(reader: &mut Reader<&[u8]>, position: u64) -> io::Result<u32> {
+    reader.set_position(position);
+    reader.read_u32_be()
+}
+
+/// Parses the TAGX section following the header of the first INDX record,
+/// returning the number of control bytes of entries and tag definitions
+fn parse_tagx(header: &[u8], offset: usize) -> io::Result<(usize, Vec<TagDefinition>)> {
+    let tagx = header
+        .get(offset..)
+        .filter(|tagx| tagx.len() >= 12 && tagx.starts_with(TAGX_MAGIC))
+        .ok_or_else(|| invalid("index doesn't have a valid TAGX section"))?;
+    let length = u32::from_be_bytes([tagx[4], tagx[5], tagx[6], tagx[7]]) as usize;
+    let control_bytes = u32::from_be_bytes([tagx[8], tagx[9], tagx[10], tagx[11]]) as usize;
+    let definitions = tagx
+        .get(12..length.max(12))
+        .ok_or_else(|| invalid("TAGX section exceeds the record"))?
+        .chunks_exact(4)
+        .map(|d| TagDefinition {
+            tag: d[0],
+            values_per_entry: d[1],
+            mask: d[2],
+            end: d[3] & 1 != 0,
+        })
+        .collect();
+    Ok((control_bytes, definitions))
+}
+
+/// Parses entries of an INDX record, located by offsets of its IDXT section
+fn parse_entries(
+    data: &[u8],
+    control_bytes: usize,
+    definitions: &[TagDefinition],
+    entries: &mut Vec<IndexEntry>,
+) -> io::Result<()> {
+    let mut reader = Reader::new(data);
+    let idxt = read_u32_at(&mut reader, 20)? as usize;
+    let count = read_u32_at(&mut reader, 24)? as usize;
+    let offsets = data
+        .get(idxt..)
+        .filter(|offsets| offsets.starts_with(IDXT_MAGIC))
+        .and_then(|offsets| offsets.get(4..count.checked_mul(2)?.checked_add(4)?))
+        .ok_or_else(|| invalid("index doesn't have a valid IDXT section"))?;
+
+    let offsets: Vec<usize> = offsets
+        .chunks(2)
+        .map(|o| usize::from(u16::from_be_bytes([o[0], o[1]])))
+        .chain(std::iter::once(idxt))
+        .collect();
+    for bounds in offsets.windows(2) {
+        let entry = data
+            .get(bounds[0]..bounds[1])
+            .ok_or_else(|| invalid("index entry exceeds the record"))?;
+        let label_len = usize::from(*entry.first().ok_or_else(|| invalid("empty index entry"))?);
+        let label = entry
+            .get(1..1 + label_len)
+            .ok_or_else(|| invalid("index entry label exceeds the entry"))?;
+        entries.push(IndexEntry {
+            label: label.to_vec(),
+            tags: parse_tags(&entry[1 + label_len..], control_bytes, definitions)?,
+        });
+    }
+    Ok(())
+}
+
+/// Reads values of tags following the label of an entry. Bits of control
+/// bytes selected by masks of tags hold numbers of their values, or tell that
+/// a length in bytes of the values follows the control bytes.
+fn parse_tags(data: &[u8], control_bytes: usize, definitions: &[TagDefinition]) -> io::Result<Vec<(u8, Vec<u32>)>> {
+    enum Count {
+        Values(u32),
+        Bytes(u32),
+    }
+
+    let controls = data
+        .get(..control_bytes)
+        .ok_or_else(|| invalid("index entry control bytes exceed the entry"))?;
+    let mut pos = control_bytes;
+    let mut counts = Vec::new();
+    let mut control = 0;
+    for definition in definitions {
+        if definition.end {
+            control += 1;
+            continue;
+        }
+        let mask = definition.mask;
+        let value = controls.get(control).map_or(0, |c| c & mask);
+        if value == 0 {
+            continue;
+        }
+        let count = if value == mask && mask.count_ones() > 1 {
+            let (bytes, consumed) = read_var_width(data, pos)?;
+            pos += consumed;
+            Count::Bytes(bytes)
+        } else {
+            Count::Values(u32::from(value >> mask.trailing_zeros()) * u32::from(definition.values_per_entry))
+        };
+        counts.push((definition.tag, count));
+    }
+
+    let mut tags = Vec::with_capacity(counts.len());
+    for (tag, count) in counts {
+        let mut values = Vec::new();
+        match count {
+            Count::Values(count) => {
+                for _ in 0..count {
+                    let (value, consumed) = read_var_width(data, pos)?;
+                    pos += consumed;
+                    values.push(value);
+                }
+            }
+            Count::Bytes(bytes) => {
+                let end = pos.saturating_add(bytes as usize);
+                while pos < end {
+                    let (value, consumed) = read_var_width(data, pos)?;
+                    pos += consumed;
+                    values.push(value);
+                }
+            }
+        }
+        tags.push((tag, values));
+    }
+    Ok(tags)
+}
+
+/// Reads a number stored in 7 bit groups, the last of which has the highest
+/// bit set, returning it with the number of bytes read
+fn read_var_width(data: &[u8], pos: usize) -> io::Result<(u32, usize)> {
+    let mut value: u32 = 0;
+    for (i, &byte) in data.get(pos..).unwrap_or_default().iter().take(5).enumerate() {
+        value = value.wrapping_shl(7) | u32::from(byte & 0x7F);
+        if byte & 0x80 != 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(invalid("invalid variable width number in index entry"))
+}
+
+fn missing_record(index: usize) -> io::Error {
+    invalid(&format!("index record {} does not exist", index))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::book;
+
+    /// Returns an index entry with `label` followed by encoded tags
+    pub(crate) fn entry(label: &[u8], tags: &[u8]) -> Vec<u8> {
+        let mut entry = vec![label.len() as u8];
+        entry.extend_from_slice(label);
+        entry.extend_from_slice(tags);
+        entry
+    }
+
+    /// Builds records of an index with a single control byte, `tags`
+    /// definitions and `entries` in one INDX record, followed by `cncx` records.
+    /// Labels are decoded with `ordt` characters if passed.
+    pub(crate) fn records(
+        tags: &[[u8; 4]],
+        entries: &[Vec<u8>],
+        cncx: &[Vec<u8>],
+        ordt: Option<&[u16]>,
+    ) -> Vec<Vec<u8>> {
+        fn put(buf: &mut [u8], offset: usize, value: u32) {
+            buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        const HEADER_LEN: usize = 192;
+
+        let mut header = vec![0; HEADER_LEN];
+        header[..4].copy_from_slice(b"INDX");
+        put(&mut header, 4, HEADER_LEN as u32);
+        put(&mut header, 24, 1);
+        put(&mut header, 28, 65001);
+        put(&mut header, 36, entries.len() as u32);
+        put(&mut header, 52, cncx.len() as u32);
+        header.extend_from_slice(b"TAGX");
+        header.extend_from_slice(&(16 + 4 * tags.len() as u32).to_be_bytes());
+        header.extend_from_slice(&1u32.to_be_bytes());
+        for tag in tags {
+            header.extend_from_slice(tag);
+        }
+        header.extend_from_slice(&[0, 0, 0, 1]);
+        if let Some(chars) = ordt {
+            put(&mut header, 168, chars.len() as u32);
+            let collation = header.len() as u32;
+            put(&mut header, 172, collation);
+            header.extend_from_slice(b"ORDT");
+            header.extend((0..chars.len()).map(|i| i as u8));
+            let chars_offset = header.len() as u32;
+            put(&mut header, 176, chars_offset);
+            header.extend_from_slice(b"ORDT");
+            for c in chars {
+                header.extend_from_slice(&c.to_be_bytes());
+            }
+        }
+
+        let mut data = vec![0; HEADER_LEN];
+        data[..4].copy_from_slice(b"INDX");
+        put(&mut data, 4, HEADER_LEN as u32);
+        put(&mut data, 24, entries.len() as u32);
+        let mut offsets = Vec::new();
+        for entry in entries {
+            offsets.push(data.len() as u16);
+            data.extend_from_slice(entry);
+        }
+        let idxt = data.len() as u32;
+        put(&mut data, 20, idxt);
+        data.extend_from_slice(b"IDXT");
+        for offset in offsets {
+            data.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        let mut records = vec![header, data];
+        records.extend(cncx.iter().cloned());
+        records
+    }
+
+    #[test]
+    fn parses_tags() {
+        let definitions = [
+            TagDefinition {
+                tag: 1,
+                values_per_entry: 1,
+                mask: 0x01,
+                end: false,
+            },
+            TagDefinition {
+                tag: 2,
+                values_per_entry: 2,
+                mask: 0x02,
+                end: false,
+            },
+            TagDefinition {
+                tag: 42,
+                values_per_entry: 1,
+                mask: 0x0C,
+                end: false,
+            },
+            TagDefinition {
+                tag: 0,
+                values_per_entry: 0,
+                mask: 0,
+                end: true,
+            },
+        ];
+        // Tag 42 has all bits of its mask set, so a length of its values follows
+        let tags = parse_tags(b"\x0f\x82\x81\x01\x82\x83\x84\x85", 1, &definitions).unwrap();
+        assert_eq!(tags, vec![(1, vec![1]), (2, vec![130, 3]), (42, vec![4, 5])]);
+        let tags = parse_tags(b"\x08\x85\x86", 1, &definitions).unwrap();
+        assert_eq!(tags, vec![(42, vec![5, 6])]);
+        assert!(parse_tags(b"\x01", 1, &definitions).is_err());
+    }
+
+    #[test]
+    fn parses_index() {
+        let entries = [entry(b"\x01\x02", b"\x01\x85"), entry(b"b", b"\x00")];
+        let mut cncx = vec![0; 3];
+        cncx.extend_from_slice(b"\x84name");
+        let mut records = vec![book::Record0::default().build()];
+        records.extend(self::records(
+            &[[1, 1, 1, 0]],
+            &entries,
+            &[cncx],
+            Some(&[0x61, 0x17C, 0x119]),
+        ));
+        let content = book::pdb(&records, 2);
+        let m = Mobi::new(&content).unwrap();
+
+        let index = Index::parse(&m, 1).unwrap();
+        assert_eq!(index.entries.len(), 2);
+        assert_eq!(index.entries[0].tag(1), Some(&[5][..]));
+        assert_eq!(index.entries[1].tag(1), None);
+        assert_eq!(index.decode_label(&index.entries[0].label), "żę");
+        assert_eq!(index.decode_label(&index.entries[1].label), "b");
+        assert_eq!(index.label_units(&index.entries[0].label), vec![0x17C, 0x119]);
+        assert_eq!(index.cncx_string(3).as_deref(), Some("name"));
+        assert_eq!(index.cncx_string(0x10000), None);
+        assert!(Index::parse(&m, 0).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use crate::index::{Index, IndexEntry};
+use std::io;
+
+/// Tag of orthographic index entries holding indexes of their inflection groups
+const INFLECTION_GROUPS_TAG: u8 = 0x2A;
+/// Tag of inflection groups holding CNCX offsets of names of their rules
+const NAMES_TAG: u8 = 0x05;
+/// Tag of inflection groups holding indexes of entries with their rules
+const RULES_TAG: u8 = 0x1A;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Inflected form of a dictionary headword
+pub struct Inflection {
+    /// Name of the inflection rule, for example of a grammatical case
+    pub name: String,
+    pub form: String,
+}
+
+/// Returns inflected forms of the orthographic index `entry`, applying rules
+/// of its groups in the inflection index to its headword. Forms which rules
+/// don't apply to are skipped, while references to entries missing from the
+/// inflection index are an error.
+pub(crate) fn inflections(orth: &Index, infl: &Index, entry: &IndexEntry) -> io::Result<Vec<Inflection>> {
+    let mut inflections = Vec::new();
+    let headword = orth.label_units(&entry.label);
+    for &group in entry.tag(INFLECTION_GROUPS_TAG).unwrap_or_default() {
+        let group = infl_entry(infl, group)?;
+        let names = group.tag(NAMES_TAG).unwrap_or_default();
+        for (i, &rule) in group.tag(RULES_TAG).unwrap_or_default().iter().enumerate() {
+            let rule = &infl_entry(infl, rule)?.label;
+            if let Some(form) = apply_rule(&headword, rule, |byte| infl.code_unit(byte)) {
+                let name = names.get(i).and_then(|&name| infl.cncx_string(name));
+                inflections.push(Inflection {
+                    name: name.unwrap_or_default(),
+                    form: orth.decode_units(&form),
+                });
+            }
+        }
+    }
+    Ok(inflections)
+}
+
+fn infl_entry(infl: &Index, index: u32) -> io::Result<&IndexEntry> {
+    infl.entries.get(index as usize).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("inflection index entry {} does not exist", index),
+        )
+    })
+}
+
+/// Applies an inflection rule to `word`, returning `None` if it doesn't apply.
+///
+/// Bytes up to 0x04 switch between inserting (0x01, 0x02) and deleting (0x03,
+/// 0x04) characters at the start (0x01, 0x04) or the end (0x02, 0x03) of the
+/// word, bytes from 0x0A to 0x13 move the cursor back from the end of the
+/// word, and the rest are characters inserted or deleted at the cursor.
+/// Characters of the end of the word are stored in reverse order, and are
+/// mapped to characters of the word by `unit`.
+fn apply_rule(word: &[u16], rule: &[u8], unit: impl Fn(u8) -> u16) -> Option<Vec<u16>> {
+    let mut word = word.to_vec();
+    let mut mode = None;
+    let mut pos = word.len();
+    for &byte in rule {
+        let at_end = mode == Some(0x02) || mode == Some(0x03);
+        match byte {
+            0x01 | 0x04 => {
+                if mode != Some(0x01) && mode != Some(0x04) {
+                    pos = 0;
+                }
+                mode = Some(byte);
+            }
+            0x02 | 0x03 => {
+                if !at_end {
+                    pos = word.len();
+                }
+                mode = Some(byte);
+            }
+            0x0A..=0x13 => {
+                if !at_end {
+                    mode = Some(0x02);
+                    pos = word.len();
+                }
+                pos = pos.checked_sub(usize::from(byte - 0x0A))?;
+            }
+            0x14..=0xFF => {
+                let c = unit(byte);
+                match mode? {
+                    0x01 => {
+                        word.insert(pos, c);
+                        pos += 1;
+                    }
+                    0x02 => word.insert(pos, c),
+                    0x03 => {
+                        pos = pos.checked_sub(1)?;
+                        if word.get(pos) != Some(&c) {
+                            return None;
+                        }
+                        word.remove(pos);
+                    }
+                    _ => {
+                        if word.get(pos) != Some(&c) {
+                            return None;
+                        }
+                        word.remove(pos);
+                    }
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(word: &str, rule: &[u8]) -> Option<String> {
+        let word: Vec<u16> = word.encode_utf16().collect();
+        apply_rule(&word, rule, u16::from).map(|form| String::from_utf16_lossy(&form))
+    }
+
+    #[test]
+    fn applies_rules() {
+        assert_eq!(apply("run", b"\x02gnin").as_deref(), Some("running"));
+        assert_eq!(apply("run", b"\x0b\x03u\x02a").as_deref(), Some("ran"));
+        assert_eq!(apply("run", b"\x01re").as_deref(), Some("rerun"));
+        assert_eq!(apply("unset", b"\x04un").as_deref(), Some("set"));
+        assert_eq!(apply("men", b"\x03ne\x02na").as_deref(), Some("man"));
+
+        assert_eq!(apply("run", b"\x03x"), None);
+        assert_eq!(apply("run", b"x"), None);
+        assert_eq!(apply("run", b"\x0f"), None);
+        assert_eq!(apply("run", b"\x00"), None);
+    }
+
+    #[test]
+    fn maps_rule_characters() {
+        let word: Vec<u16> = "жена".encode_utf16().collect();
+        // Codes of an ORDT table starting with Cyrillic "а"
+        let unit = |byte: u8| {
+            if byte >= 0x20 {
+                0x0430 + u16::from(byte - 0x20)
+            } else {
+                u16::from(byte)
+            }
+        };
+        let form = apply_rule(&word, b"\x03\x20\x02\x20\x23", unit).unwrap();
+        assert_eq!(String::from_utf16_lossy(&form), "женга");
+    }
+}
@@ -0,0 +1,184 @@
+use crate::headers::MobiMetadata;
+use crate::trailer::EOF_RECORD;
+use crate::Source;
+use std::io;
+
+/// Length of the magic bytes prefix needed to classify a record
+pub(crate) const MAGIC_LEN: usize = 8;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// Kind of content held by a PDB record
+pub enum RecordKind {
+    /// Record 0 of a book, holding PalmDOC, MOBI and EXTH headers
+    Header,
+    Text,
+    Image,
+    Font,
+    /// Audio clip of enhanced books, in an AUDI record
+    Audio,
+    /// Video clip of enhanced books, in a VIDE record
+    Video,
+    /// INDX record of an index (NCX, skeleton, fragment, dictionary...)
+    Index,
+    Huff,
+    Cdic,
+    Flis,
+    Fcis,
+    /// Archive of sources used by kindlegen to build the book
+    Srcs,
+    Datp,
+    Resc,
+    Fdst,
+    /// Record separating MOBI and KF8 parts of a joint file
+    Boundary,
+    Eof,
+    Unknown,
+}
+
+impl RecordKind {
+    /// Classifies a non text record using its leading bytes
+    pub(crate) fn from_magic(magic: &[u8]) -> RecordKind {
+        macro_rules! starts {
+            ($m: expr) => {
+                magic.starts_with($m)
+            };
+        }
+
+        if starts!(b"BOUNDARY") {
+            RecordKind::Boundary
+        } else if starts!(EOF_RECORD) {
+            RecordKind::Eof
+        } else if starts!(b"FLIS") {
+            RecordKind::Flis
+        } else if starts!(b"FCIS") {
+            RecordKind::Fcis
+        } else if starts!(b"SRCS") {
+            RecordKind::Srcs
+        } else if starts!(b"DATP") {
+            RecordKind::Datp
+        } else if starts!(b"RESC") {
+            RecordKind::Resc
+        } else if starts!(b"FDST") {
+            RecordKind::Fdst
+        } else if starts!(b"INDX") {
+            RecordKind::Index
+        } else if starts!(b"HUFF") {
+            RecordKind::Huff
+        } else if starts!(b"CDIC") {
+            RecordKind::Cdic
+        } else if starts!(b"FONT") {
+            RecordKind::Font
+        } else if starts!(b"AUDI") {
+            RecordKind::Audio
+        } else if starts!(b"VIDE") {
+            RecordKind::Video
+        } else if starts!(b"\xff\xd8\xff") || starts!(b"GIF8") || starts!(b"\x89PNG") || starts!(b"BM") {
+            RecordKind::Image
+        } else {
+            RecordKind::Unknown
+        }
+    }
+
+    /// Classifies every record of a book, reading magic bytes of records from
+    /// `source` of content of length `len`
+    pub(crate) fn read(metadata: &MobiMetadata, source: &mut impl Source, len: u64) -> io::Result<Vec<RecordKind>> {
+        RecordKind::classify(metadata, |i| match RecordKind::magic_range(metadata, i, len) {
+            Some((offset, len)) => source.read_at(offset, len),
+            None => Ok(vec![]),
+        })
+    }
+
+    /// Returns the offset and length of magic bytes of the record at `index`,
+    /// in content of length `len`
+    pub(crate) fn magic_range(metadata: &MobiMetadata, index: usize, len: u64) -> Option<(u64, usize)> {
+        let (start, end) = metadata.records.bounds(index, len)?;
+        Some((start, (end - start).min(MAGIC_LEN as u64) as usize))
+    }
+
+    /// Classifies every record of a book. Header and text records are located
+    /// using the headers, while the rest is identified by its magic bytes,
+    /// the first `MAGIC_LEN` bytes of which `magic` returns for a record index.
+    pub(crate) fn classify(
+        metadata: &MobiMetadata,
+        mut magic: impl FnMut(usize) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Vec<RecordKind>> {
+        let count = metadata.records.records.len();
+        let mut kinds = vec![RecordKind::Unknown; count];
+
+        let mut mark_headers = |record0: usize, record_count: u16| {
+            if let Some(kind) = kinds.get_mut(record0) {
+                *kind = RecordKind::Header;
+            }
+            let text_end = (record0 + 1 + record_count as usize).min(count);
+            for kind in kinds.iter_mut().take(text_end).skip(record0 + 1) {
+                *kind = RecordKind::Text;
+            }
+        };
+
+        mark_headers(0, metadata.palmdoc.record_count);
+        if let Some(kf8) = &metadata.kf8 {
+            mark_headers(kf8.record0 as usize, kf8.palmdoc.record_count);
+        }
+
+        for (i, kind) in kinds.iter_mut().enumerate() {
+            if *kind == RecordKind::Unknown {
+                *kind = RecordKind::from_magic(&magic(i)?);
+            }
+        }
+
+        Ok(kinds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+
+    #[test]
+    fn from_magic() {
+        assert_eq!(RecordKind::from_magic(b"BOUNDARY"), RecordKind::Boundary);
+        assert_eq!(RecordKind::from_magic(EOF_RECORD), RecordKind::Eof);
+        assert_eq!(RecordKind::from_magic(b"FLIS\0\0\0\x08"), RecordKind::Flis);
+        assert_eq!(RecordKind::from_magic(b"AUDI\0\0\0\x0c"), RecordKind::Audio);
+        assert_eq!(RecordKind::from_magic(b"\xff\xd8\xff\xe0\0\x10JF"), RecordKind::Image);
+        assert_eq!(RecordKind::from_magic(b"\x89PNG\r\n\x1a\n"), RecordKind::Image);
+        assert_eq!(RecordKind::from_magic(b"\0\0"), RecordKind::Unknown);
+        assert_eq!(RecordKind::from_magic(b""), RecordKind::Unknown);
+    }
+
+    #[test]
+    fn classify() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            ..Default::default()
+        };
+        let records = [
+            record0.build(),
+            b"FLIS looks like text".to_vec(),
+            b"text".to_vec(),
+            b"\0\0".to_vec(),
+            b"GIF89a".to_vec(),
+            b"FLIS\0\0\0\x08".to_vec(),
+            b"FCIS\0\0\0\x14".to_vec(),
+            EOF_RECORD.to_vec(),
+        ];
+        let content = book::pdb(&records, 2);
+        let metadata = MobiMetadata::new(&content).unwrap();
+
+        let kinds = RecordKind::read(&metadata, &mut &content[..], content.len() as u64).unwrap();
+        assert_eq!(
+            kinds,
+            vec![
+                RecordKind::Header,
+                RecordKind::Text,
+                RecordKind::Text,
+                RecordKind::Unknown,
+                RecordKind::Image,
+                RecordKind::Flis,
+                RecordKind::Fcis,
+                RecordKind::Eof,
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use crate::headers::{MobiMetadata, TextEncoding};
+use crate::reader::Reader;
+use crate::record::{self, Record, TextRecords};
+use crate::source::{SeekSource, Source};
+use crate::{text, Diagnostics, ParseOptions, RecordKind, TextReader};
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+#[derive(Debug)]
+/// Structure that holds parsed ebook information, reading and decompressing records
+/// from the underlying source only when they are requested. Unlike `Mobi` it never
+/// holds the whole book in memory, which makes it suitable for large files.
+pub struct LazyMobi<S> {
+    source: S,
+    len: u64,
+    pub metadata: MobiMetadata,
+    options: ParseOptions,
+    /// Kinds of records, found out on first use
+    kinds: Option<Vec<RecordKind>>,
+}
+
+impl LazyMobi<File> {
+    /// Construct a LazyMobi object from passed file path
+    pub fn from_path<P: AsRef<Path>>(file_path: P) -> io::Result<LazyMobi<File>> {
+        LazyMobi::from_source(File::open(file_path)?)
+    }
+}
+
+impl<R: Read + Seek> LazyMobi<SeekSource<R>> {
+    /// Construct a LazyMobi object from an object that implements Read and Seek traits.
+    /// Only headers are parsed at this point.
+    pub fn new(reader: R) -> io::Result<LazyMobi<SeekSource<R>>> {
+        LazyMobi::from_source(SeekSource::new(reader))
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.source.into_inner()
+    }
+}
+
+impl<S: Source> LazyMobi<S> {
+    /// Construct a LazyMobi object from any source of content. Only headers are
+    /// parsed at this point.
+    pub fn from_source(source: S) -> io::Result<LazyMobi<S>> {
+        LazyMobi::from_source_with_options(source, &ParseOptions::default())
+    }
+
+    /// Construct a LazyMobi object from any source of content, parsed with passed
+    /// options. Skipping content has no effect, as it is read only when requested.
+    pub fn from_source_with_options(mut source: S, options: &ParseOptions) -> io::Result<LazyMobi<S>> {
+        let metadata = MobiMetadata::from_reader(&mut Reader::new(&mut source), options)?;
+        let len = source.size()?;
+        options.check_file_size(len)?;
+        Ok(LazyMobi {
+            source,
+            len,
+            metadata,
+            options: options.clone(),
+            kinds: None,
+        })
+    }
+
+    /// Returns the underlying source
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    /// Returns number of PDB records in the book
+    pub fn record_count(&self) -> usize {
+        self.metadata.records.records.len()
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let buf = self.source.read_at(offset, len)?;
+        if buf.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+        Ok(buf)
+    }
+
+    /// Reads raw content of the PDB record located at `index`
+    pub fn raw_record(&mut self, index: usize) -> io::Result<Vec<u8>> {
+        let (start, end) = self
+            .metadata
+            .records
+            .bounds(index, self.len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("record {} does not exist", index)))?;
+        self.read_at(start, (end - start) as usize)
+    }
+
+    /// Reads and decompresses the text record located at `index`. Returns an
+    /// error if the record doesn't exist or doesn't hold text.
+    pub fn text_record(&mut self, index: usize) -> io::Result<Record> {
+        if self.kinds()?.get(index) != Some(&RecordKind::Text) {
+            return Err(record::not_text_record(index));
+        }
+        let text = TextRecords::new(&self.metadata, &self.options, self.len);
+        text.read(&mut self.source, index, &mut Diagnostics::new())
+    }
+
+    /// Returns the kind of content held by each record of the book. Only leading
+    /// bytes of records are read to find it out, the first time kinds are needed.
+    pub fn record_kinds(&mut self) -> io::Result<Vec<RecordKind>> {
+        Ok(self.kinds()?.to_vec())
+    }
+
+    fn kinds(&mut self) -> io::Result<&[RecordKind]> {
+        if self.kinds.is_none() {
+            self.kinds = Some(RecordKind::read(&self.metadata, &mut self.source, self.len)?);
+        }
+        Ok(self.kinds.as_deref().unwrap_or_default())
+    }
+
+    fn text_record_indexes(&mut self) -> io::Result<Vec<usize>> {
+        self.kinds()?;
+        Ok(self
+            .metadata
+            .text_record_indexes(self.kinds.as_deref().unwrap_or_default()))
+    }
+
+    /// Returns a reader over the text of the book, reading and decompressing text
+    /// records one at a time while it is being read. Records which can't be
+    /// decompressed are skipped, unless parsing is strict.
+    pub fn text_reader(&mut self) -> io::Result<TextReader<'_>> {
+        let indexes = self.text_record_indexes()?;
+        let text = TextRecords::new(&self.metadata, &self.options, self.len);
+        Ok(text.text_reader(&mut self.source, indexes))
+    }
+
+    /// Reads every text record of the book, one after another. Records which
+    /// can't be decompressed end up empty, unless parsing is strict.
+    fn records(&mut self, diagnostics: &mut Diagnostics) -> io::Result<Vec<Record>> {
+        let indexes = self.text_record_indexes()?;
+        let text = TextRecords::new(&self.metadata, &self.options, self.len);
+        text.read_all(&mut self.source, &indexes, diagnostics)
+    }
+
+    /// Returns anomalies found while parsing the book leniently, both in headers
+    /// and in text records, which are read and decompressed to find them.
+    pub fn diagnostics(&mut self) -> io::Result<Diagnostics> {
+        let mut diagnostics = self.metadata.diagnostics.clone();
+        self.records(&mut diagnostics)?;
+        Ok(diagnostics)
+    }
+
+    /// Returns all readable records content decompressed as a String.
+    /// There are only two supported encodings in mobi format (UTF8, WIN1252)
+    /// and both are losely converted by this function
+    pub fn content_as_string_lossy(&mut self) -> io::Result<String> {
+        let records = self.records(&mut Diagnostics::new())?;
+        Ok(text::decode_records_lossy(&records, self.text_encoding()))
+    }
+
+    /// Returns all readable records content decompressed as a String.
+    /// This function is a strict version returning error on first encountered
+    /// decoding error, unless replacing them was chosen with `ParseOptions::decode_errors`.
+    pub fn content_as_string(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let records = self.records(&mut Diagnostics::new())?;
+        Ok(text::decode_records_with(
+            &records,
+            self.text_encoding(),
+            self.options.decode_errors,
+        )?)
+    }
+
+    /// Returns all readable records content decompressed as a Vec
+    pub fn content(&mut self) -> io::Result<Vec<u8>> {
+        Ok(text::join_records(&self.records(&mut Diagnostics::new())?))
+    }
+
+    /// Returns text encoding used in ebook
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.metadata.text_encoding()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{book, DecodeErrors, DiagnosticKind, Mobi};
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_records_on_demand() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            compression: 2,
+            exth: vec![(100, b"Author".to_vec())],
+            ..Default::default()
+        };
+        let records = [
+            record0.build(),
+            b"\x03abc".to_vec(),
+            b"def".to_vec(),
+            b"GIF89a".to_vec(),
+        ];
+        let mut m = LazyMobi::new(Cursor::new(book::pdb(&records, 2))).unwrap();
+        let mut from_source = LazyMobi::from_source(book::pdb(&records, 2)).unwrap();
+        assert_eq!(from_source.content().unwrap(), b"abcdef");
+
+        assert_eq!(m.metadata.author(), Some(String::from("Author")));
+        assert_eq!(m.record_count(), 4);
+        assert_eq!(m.raw_record(3).unwrap(), b"GIF89a");
+        assert_eq!(m.text_record(1).unwrap().record_data, b"abc");
+        assert!(m.text_record(3).is_err());
+        assert_eq!(m.record_kinds().unwrap()[3], RecordKind::Image);
+        assert_eq!(m.content().unwrap(), b"abcdef");
+        assert_eq!(m.content_as_string().unwrap(), "abcdef");
+
+        let mut text = String::new();
+        m.text_reader().unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "abcdef");
+    }
+
+    #[test]
+    fn recovers_like_mobi() {
+        let record0 = book::Record0 {
+            record_count: 3,
+            compression: 2,
+            ..Default::default()
+        };
+        // Back reference of the second record points before the start of its text
+        let records = [
+            record0.build(),
+            b"\x03abc".to_vec(),
+            b"d\x80\x20".to_vec(),
+            b"\x02\xc3\xa9".to_vec(),
+        ];
+        let book = book::pdb(&records, 2);
+        let m = Mobi::new(&book).unwrap();
+        let mut lazy = LazyMobi::from_source(book.clone()).unwrap();
+
+        assert_eq!(lazy.content().unwrap(), m.content().unwrap());
+        assert_eq!(lazy.content_as_string().unwrap(), m.content_as_string().unwrap());
+        assert_eq!(lazy.diagnostics().unwrap(), m.diagnostics().unwrap());
+        assert!(lazy
+            .diagnostics()
+            .unwrap()
+            .contains(DiagnosticKind::InvalidBackReference));
+        let mut text = Vec::new();
+        lazy.text_reader().unwrap().read_to_end(&mut text).unwrap();
+        assert_eq!(text, m.content().unwrap());
+
+        // Text split in the middle of a character is only decoded with replacements if chosen
+        let mut records = records.to_vec();
+        records[3] = b"\x02\xc3".to_vec();
+        let book = book::pdb(&records, 2);
+        let options = ParseOptions::new().decode_errors(DecodeErrors::Replace);
+        let m = Mobi::new_with_options(&book, &options).unwrap();
+        let mut lazy = LazyMobi::from_source_with_options(book.clone(), &options).unwrap();
+        assert_eq!(lazy.content_as_string().unwrap(), m.content_as_string().unwrap());
+        assert!(LazyMobi::from_source(book.clone())
+            .unwrap()
+            .content_as_string()
+            .is_err());
+
+        let options = ParseOptions::new().strict();
+        let mut lazy = LazyMobi::from_source_with_options(book.clone(), &options).unwrap();
+        assert!(lazy.content().is_err());
+        assert!(Mobi::new_with_options(&book, &options).unwrap().content().is_err());
+    }
+
+    #[test]
+    fn reads_record_kinds_once() {
+        struct Counting(Vec<u8>, usize);
+        impl Source for Counting {
+            fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+                self.1 += 1;
+                self.0.read_at(offset, len)
+            }
+            fn size(&mut self) -> io::Result<u64> {
+                self.0.size()
+            }
+        }
+
+        let record0 = book::Record0 {
+            record_count: 2,
+            ..Default::default()
+        };
+        let records = [record0.build(), b"abc".to_vec(), b"def".to_vec()];
+        let mut m = LazyMobi::from_source(Counting(book::pdb(&records, 2), 0)).unwrap();
+        m.text_record(1).unwrap();
+        let reads = m.source.1;
+        m.text_record(1).unwrap();
+        m.text_record(2).unwrap();
+        assert_eq!(m.source.1, reads + 2);
+        assert_eq!(m.content().unwrap(), b"abcdef");
+        assert_eq!(m.source.1, reads + 4);
+    }
+}
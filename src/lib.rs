@@ -12,6 +12,8 @@
 //!     let book = vec![0, 0, 0];
 //!     // You can either create a Mobi struct from a slice
 //!     let m = Mobi::new(&book)?;
+//!     // Or borrow its contents without copying them
+//!     let m = Mobi::from_slice(&book)?;
 //!     // Or from an instance of io::Read
 //!     let stdin = std::io::stdin();
 //!     let mut handle = stdin.lock();
@@ -45,54 +47,180 @@
 /// Module with headers from book containg more extracted data not
 /// available through public API.
 pub mod headers;
-pub use headers::MobiMetadata;
-pub use record::Record;
+/// Module with PalmDOC LZ77 decompression used by text records.
+pub mod lz77;
+/// Module with sources of book content that books can be read from.
+pub mod source;
+#[cfg(feature = "tokio")]
+pub use async_io::AsyncLazyMobi;
+pub use diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, HeaderKind};
+pub use dictionary::{Dictionary, DictionaryEntry};
+pub use font::{Font, FontFormat};
+pub use headers::{Compression, Encryption, MobiMetadata, MobiType};
+pub use inflection::Inflection;
+pub use kind::RecordKind;
+pub use lazy::LazyMobi;
+pub use options::{DecodeErrors, LimitExceeded, ParseMode, ParseOptions};
+pub use record::{RawRecord, Record};
+pub use repair::Recovered;
+pub use resource::{Resource, ResourceKind};
+pub use source::{SeekSource, Source};
+pub use srcs::SourceFile;
+pub use text::{DecodedText, TextDecoder, TextReader};
+pub use trailer::{Fcis, Flis, EOF_RECORD};
+pub use validate::{Severity, ValidationIssue, ValidationReport, ValidationRule};
 
+#[cfg(feature = "tokio")]
+pub(crate) mod async_io;
 pub(crate) mod book;
+pub(crate) mod diagnostics;
+pub(crate) mod dictionary;
 #[cfg(feature = "fmt")]
 mod display;
-pub(crate) mod lz77;
+pub(crate) mod font;
+pub(crate) mod index;
+pub(crate) mod inflection;
+pub(crate) mod kind;
+pub(crate) mod lazy;
+pub(crate) mod options;
 pub(crate) mod reader;
 pub(crate) mod record;
-use crate::reader::{MobiReader, ReaderPrime};
+pub(crate) mod repair;
+pub(crate) mod resource;
+pub(crate) mod srcs;
+pub(crate) mod text;
+pub(crate) mod trailer;
+pub(crate) mod validate;
 #[cfg(feature = "time")]
 use chrono::NaiveDateTime;
 use headers::TextEncoding;
 pub(crate) use reader::Reader;
-use std::fs::File;
-use std::io::BufReader;
-use std::{io, io::Read, ops::Range, path::Path};
+use record::TextRecords;
+use std::{borrow::Cow, fs::File, io, io::Read, path::Path};
 
 #[derive(Debug, Default)]
-/// Structure that holds parsed ebook information and contents
-pub struct Mobi {
-    pub content: Vec<u8>,
+/// Structure that holds parsed ebook information and contents. Contents are either
+/// owned or borrowed from a slice passed to `Mobi::from_slice`.
+pub struct Mobi<'a> {
+    pub content: Cow<'a, [u8]>,
     pub metadata: MobiMetadata,
+    options: ParseOptions,
+    /// Kinds of records, found out once when the book is parsed
+    kinds: Vec<RecordKind>,
 }
-impl Mobi {
+impl Mobi<'static> {
     /// Construct a Mobi object from a slice of bytes
-    pub fn new<B: AsRef<Vec<u8>>>(bytes: B) -> io::Result<Mobi> {
-        Mobi::from_reader(&mut Reader::new(bytes.as_ref()))
+    pub fn new<B: AsRef<Vec<u8>>>(bytes: B) -> io::Result<Mobi<'static>> {
+        Mobi::new_with_options(bytes, &ParseOptions::default())
+    }
+
+    /// Construct a Mobi object from a slice of bytes, parsed with passed options
+    pub fn new_with_options<B: AsRef<Vec<u8>>>(bytes: B, options: &ParseOptions) -> io::Result<Mobi<'static>> {
+        Mobi::from_content(Cow::Owned(bytes.as_ref().clone()), options)
     }
 
     /// Construct a Mobi object from passed file path
-    pub fn from_path<P: AsRef<Path>>(file_path: P) -> io::Result<Mobi> {
-        let mut reader = ReaderPrime::new(BufReader::new(File::open(file_path)?));
-        Mobi::from_reader(&mut reader)
+    pub fn from_path<P: AsRef<Path>>(file_path: P) -> io::Result<Mobi<'static>> {
+        Mobi::from_path_with_options(file_path, &ParseOptions::default())
+    }
+
+    /// Construct a Mobi object from passed file path, parsed with passed options.
+    /// When content is skipped only headers are read from the file.
+    pub fn from_path_with_options<P: AsRef<Path>>(file_path: P, options: &ParseOptions) -> io::Result<Mobi<'static>> {
+        if options.skip_content {
+            let metadata = MobiMetadata::from_path_with_options(file_path, options)?;
+            return Ok(Mobi::without_content(metadata, options));
+        }
+        let file = File::open(file_path)?;
+        options.check_file_size(file.metadata()?.len())?;
+        Mobi::from_read_with_options(file, options)
     }
 
     /// Construct a Mobi object from an object that implements a Read trait
-    pub fn from_read<R: Read>(reader: R) -> io::Result<Mobi> {
-        // Temporary solution
-        Mobi::from_reader(&mut ReaderPrime::new(reader))
+    pub fn from_read<R: Read>(reader: R) -> io::Result<Mobi<'static>> {
+        Mobi::from_read_with_options(reader, &ParseOptions::default())
+    }
+
+    /// Construct a Mobi object from an object that implements a Read trait, parsed
+    /// with passed options. When content is skipped the reader is consumed only up
+    /// to the end of headers.
+    pub fn from_read_with_options<R: Read>(reader: R, options: &ParseOptions) -> io::Result<Mobi<'static>> {
+        if options.skip_content {
+            let metadata = MobiMetadata::from_read_with_options(reader, options)?;
+            return Ok(Mobi::without_content(metadata, options));
+        }
+        let mut content = Vec::new();
+        // Reading a byte past the limit is enough to tell it's exceeded
+        reader
+            .take(options.max_file_size.saturating_add(1))
+            .read_to_end(&mut content)?;
+        Mobi::from_content(Cow::Owned(content), options)
+    }
+
+    fn without_content(metadata: MobiMetadata, options: &ParseOptions) -> Mobi<'static> {
+        Mobi::with_kinds(Cow::Borrowed(&[]), metadata, options)
     }
+}
+
+impl<'a> Mobi<'a> {
+    /// Construct a Mobi object borrowing contents from a slice of bytes, for example
+    /// a memory mapped file. Raw records returned by this object are slices of the
+    /// input, and only decompressed text is allocated. Whole headers are available
+    /// as raw record 0, while parsed metadata is kept in small owned structures.
+    pub fn from_slice(bytes: &'a [u8]) -> io::Result<Mobi<'a>> {
+        Mobi::from_slice_with_options(bytes, &ParseOptions::default())
+    }
+
+    /// Construct a Mobi object borrowing contents from a slice of bytes, parsed
+    /// with passed options
+    pub fn from_slice_with_options(bytes: &'a [u8], options: &ParseOptions) -> io::Result<Mobi<'a>> {
+        Mobi::from_content(Cow::Borrowed(bytes), options)
+    }
+
+    fn from_content(content: Cow<'a, [u8]>, options: &ParseOptions) -> io::Result<Mobi<'a>> {
+        options.check_file_size(content.len() as u64)?;
+        let mut metadata = MobiMetadata::from_reader(&mut Reader::new(&content[..]), options)?;
+        if options.skip_content {
+            return Ok(Mobi::with_kinds(Cow::Borrowed(&[]), metadata, options));
+        }
+
+        let len = content.len() as u64;
+        for (i, &(offset, _)) in metadata.records.records.iter().enumerate() {
+            options.check(&mut metadata.diagnostics, u64::from(offset) <= len, || {
+                Diagnostic::new(
+                    DiagnosticKind::RecordOutOfBounds,
+                    "record starts past the end of the file",
+                )
+                .header(HeaderKind::Records)
+                .record(i)
+                .offset(u64::from(offset))
+            })?;
+        }
 
-    fn from_reader(reader: &mut impl MobiReader) -> io::Result<Mobi> {
-        let metadata = MobiMetadata::from_reader(reader)?;
-        Ok(Mobi {
-            content: reader.read_to_end()?,
+        Ok(Mobi::with_kinds(content, metadata, options))
+    }
+
+    fn with_kinds(content: Cow<'a, [u8]>, metadata: MobiMetadata, options: &ParseOptions) -> Mobi<'a> {
+        let mut mobi = Mobi {
+            content,
             metadata,
-        })
+            options: options.clone(),
+            kinds: Vec::new(),
+        };
+        // Reading magic bytes out of content in memory can't fail
+        let len = mobi.content.len() as u64;
+        mobi.kinds = RecordKind::read(&mobi.metadata, &mut &mobi.content[..], len).unwrap_or_default();
+        mobi
+    }
+
+    /// Converts this object into one owning its contents
+    pub fn into_owned(self) -> Mobi<'static> {
+        Mobi {
+            content: Cow::Owned(self.content.into_owned()),
+            metadata: self.metadata,
+            options: self.options,
+            kinds: self.kinds,
+        }
     }
 
     /// Returns an author of this book
@@ -136,7 +264,7 @@ impl Mobi {
     }
 
     /// Returns type of this ebook
-    pub fn mobi_type(&self) -> Option<String> {
+    pub fn mobi_type(&self) -> MobiType {
         self.metadata.mobi_type()
     }
 
@@ -172,61 +300,293 @@ impl Mobi {
     }
 
     /// Returns compression method used on this file
-    pub fn compression(&self) -> String {
+    pub fn compression(&self) -> Compression {
         self.metadata.compression()
     }
     /// Returns encryption method used on this file
-    pub fn encryption(&self) -> String {
+    pub fn encryption(&self) -> Encryption {
         self.metadata.encryption()
     }
 
-    /// Returns last readable index of the book
-    fn last_index(&self) -> usize {
-        (self.metadata.palmdoc.record_count - 1) as usize
+    /// Returns number of PDB records in the book
+    pub fn record_count(&self) -> usize {
+        self.metadata.records.records.len()
+    }
+
+    /// Returns the raw PDB record located at `index`, or `None` if there is no such record
+    pub fn raw_record(&self, index: usize) -> Option<RawRecord<'_>> {
+        let records = &self.metadata.records;
+        let (start, end) = records.bounds(index, self.content.len() as u64)?;
+
+        Some(RawRecord::new(
+            index,
+            records.records[index],
+            &self.content[start as usize..end as usize],
+        ))
+    }
+
+    /// Returns all raw PDB records of the book
+    pub fn raw_records(&self) -> Vec<RawRecord<'_>> {
+        (0..self.record_count()).filter_map(|i| self.raw_record(i)).collect()
+    }
+
+    /// Returns decompressed content of the text record located at `index`. Returns an
+    /// error if the record doesn't exist or doesn't hold text.
+    pub fn text_record(&self, index: usize) -> io::Result<Record> {
+        if self.kinds().get(index) != Some(&RecordKind::Text) {
+            return Err(record::not_text_record(index));
+        }
+        self.text_records()
+            .read(&mut &self.content[..], index, &mut Diagnostics::new())
+    }
+
+    fn text_records(&self) -> TextRecords<'_> {
+        TextRecords::new(&self.metadata, &self.options, self.content.len() as u64)
+    }
+
+    /// Returns a reader over the text of the book, decompressing text records
+    /// one at a time while it is being read. Records which can't be read are
+    /// skipped, unless parsing is strict.
+    pub fn text_reader(&self) -> TextReader<'_> {
+        let indexes = self.metadata.text_record_indexes(self.kinds());
+        self.text_records().text_reader(&self.content[..], indexes)
+    }
+
+    /// Returns the kind of content held by each record of the book
+    pub fn record_kinds(&self) -> Vec<RecordKind> {
+        self.kinds.clone()
+    }
+
+    pub(crate) fn kinds(&self) -> &[RecordKind] {
+        &self.kinds
     }
 
-    fn readable_records_range(&self) -> Range<usize> {
-        1..self.last_index()
+    fn records(&self, diagnostics: &mut Diagnostics) -> io::Result<Vec<Record>> {
+        let indexes = self.metadata.text_record_indexes(self.kinds());
+        self.text_records().read_content(&self.content, &indexes, diagnostics)
     }
 
-    fn records(&self) -> io::Result<Vec<Record>> {
-        Record::parse_records(
-            &self.content,
-            &self.metadata.records.records,
-            self.metadata.records.extra_bytes,
-            self.metadata.palmdoc.compression_enum(),
-        )
+    /// Returns anomalies found while parsing the book leniently, both in headers
+    /// and in text and FONT records, which are unpacked to find them. Content
+    /// affected by them is skipped or guessed by other methods of this object.
+    pub fn diagnostics(&self) -> io::Result<Diagnostics> {
+        let mut diagnostics = self.metadata.diagnostics.clone();
+        self.records(&mut diagnostics)?;
+        self.read_fonts(&mut diagnostics)?;
+        Ok(diagnostics)
     }
 
     /// Returns all readable records content decompressed as a String.
     /// There are only two supported encodings in mobi format (UTF8, WIN1252)
     /// and both are losely converted by this function
     pub fn content_as_string_lossy(&self) -> io::Result<String> {
-        Ok(self.records()?[self.readable_records_range()]
-            .iter()
-            .map(|record| record.to_string_lossy(self.text_encoding()))
-            .collect())
+        Ok(text::decode_records_lossy(
+            &self.records(&mut Diagnostics::new())?,
+            self.text_encoding(),
+        ))
     }
 
     /// Returns all readable records content decompressed as a String.
     /// This function is a strict version returning error on first encountered
-    /// decoding error.
+    /// decoding error, unless replacing them was chosen with `ParseOptions::decode_errors`.
     pub fn content_as_string(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let mut content = String::new();
-        for record in &self.records()?[self.readable_records_range()] {
-            content.push_str(&record.to_string(self.text_encoding())?);
-        }
-
-        Ok(content)
+        let records = self.records(&mut Diagnostics::new())?;
+        Ok(text::decode_records_with(
+            &records,
+            self.text_encoding(),
+            self.options.decode_errors,
+        )?)
     }
 
     /// Returns all readable records content decompressed as a Vec
     pub fn content(&self) -> io::Result<Vec<u8>> {
-        let records = &self.records()?[self.readable_records_range()];
-        let mut record_data = Vec::with_capacity(records.iter().map(|r| r.record_data.len()).sum());
-        for record in records {
-            record_data.extend_from_slice(&record.record_data);
-        }
-        Ok(record_data)
+        Ok(text::join_records(&self.records(&mut Diagnostics::new())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+
+    #[test]
+    fn content_includes_every_text_record() {
+        let record0 = book::Record0 {
+            record_count: 3,
+            text_length: 9,
+            extra_data_flags: 0b10,
+            ..Default::default()
+        };
+        let records = [
+            record0.build(),
+            b"abc\x81".to_vec(),
+            b"def\x81".to_vec(),
+            b"ghi\xAA\x82".to_vec(),
+            b"\xe9\x8e\x0d\x0a".to_vec(),
+        ];
+        let m = Mobi::new(book::pdb(&records, 2)).unwrap();
+
+        assert_eq!(m.content().unwrap(), b"abcdefghi");
+        assert_eq!(m.content_as_string().unwrap(), "abcdefghi");
+        assert_eq!(m.record_kinds()[4], RecordKind::Eof);
+    }
+
+    #[test]
+    fn text_reader_streams_text_records() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            extra_data_flags: 0b10,
+            ..Default::default()
+        };
+        let records = [record0.build(), b"abc\x81".to_vec(), b"def\x81".to_vec()];
+        let m = Mobi::new(book::pdb(&records, 2)).unwrap();
+
+        let mut reader = m.text_reader();
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(reader.offset(), 3);
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"def");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(reader.offset(), 6);
+    }
+
+    #[test]
+    fn record_access() {
+        let record0 = book::Record0 {
+            record_count: 1,
+            compression: 2,
+            extra_data_flags: 0b10,
+            ..Default::default()
+        };
+        let records = [record0.build(), b"\x03abc\x81".to_vec(), b"\xe9\x8e\x0d\x0a".to_vec()];
+        let m = Mobi::new(book::pdb(&records, 2)).unwrap();
+
+        assert_eq!(m.record_count(), 3);
+        let raw = m.raw_record(1).unwrap();
+        assert_eq!(raw.index, 1);
+        assert_eq!(raw.offset, m.metadata.records.records[1].0);
+        assert_eq!(raw.unique_id, 2);
+        assert_eq!(raw.attributes, 0);
+        assert_eq!(raw.data, &records[1][..]);
+        assert_eq!(m.raw_records().last().unwrap().data, &records[2][..]);
+        assert!(m.raw_record(3).is_none());
+
+        let text = m.text_record(1).unwrap();
+        assert_eq!(text.record_data, b"abc");
+        assert_eq!(text.offset(), raw.offset);
+        assert_eq!(text.unique_id(), 2);
+        assert!(m.text_record(2).is_err());
+    }
+
+    #[test]
+    fn borrows_content() {
+        let record0 = book::Record0 {
+            record_count: 1,
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), b"text".to_vec()], 2);
+        let m = Mobi::from_slice(&book).unwrap();
+
+        assert!(matches!(m.content, Cow::Borrowed(_)));
+        let raw = m.raw_record(1).unwrap();
+        assert_eq!(raw.data.as_ptr(), book[raw.offset as usize..].as_ptr());
+        assert_eq!(m.content().unwrap(), b"text");
+
+        let m = m.into_owned();
+        assert!(matches!(m.content, Cow::Owned(_)));
+        assert_eq!(m.content_as_string().unwrap(), "text");
+    }
+
+    #[test]
+    fn parses_with_options() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            compression: 2,
+            ..Default::default()
+        };
+        let records = [record0.build(), b"\x03a\xffb".to_vec(), b"c\x80\x20".to_vec()];
+        let book = book::pdb(&records, 2);
+
+        let m = Mobi::new(&book).unwrap();
+        assert_eq!(m.content().unwrap(), b"a\xffbc");
+        assert!(m.content_as_string().is_err());
+
+        let m =
+            Mobi::from_slice_with_options(&book, &ParseOptions::new().decode_errors(DecodeErrors::Replace)).unwrap();
+        assert_eq!(m.content_as_string().unwrap(), "a\u{FFFD}bc");
+
+        let m = Mobi::new_with_options(&book, &ParseOptions::new().strict()).unwrap();
+        assert!(m.content().is_err());
+        assert!(m.text_reader().read_to_end(&mut Vec::new()).is_err());
+
+        let m = Mobi::from_read_with_options(&book[..], &ParseOptions::new().skip_content(true)).unwrap();
+        assert!(m.content.is_empty());
+        assert_eq!(m.metadata.mobi.name, "Test Book");
+
+        let truncated = &book[..book.len() - 4];
+        assert!(Mobi::from_slice(truncated).is_ok());
+        assert!(Mobi::from_slice_with_options(truncated, &ParseOptions::new().strict()).is_err());
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            compression: 2,
+            record_size: 4,
+            exth: vec![(100, b"Author".to_vec())],
+            ..Default::default()
+        };
+        let records = [record0.build(), b"abc".to_vec(), b"a\x80\x0F".to_vec()];
+        let book = book::pdb(&records, 2);
+        let limited = |options: ParseOptions| Mobi::new_with_options(&book, &options);
+
+        assert!(limited(ParseOptions::new().max_file_size(book.len() as u64)).is_ok());
+        let error = limited(ParseOptions::new().max_file_size(100)).unwrap_err();
+        assert!(error.to_string().starts_with("file size"));
+        assert!(Mobi::from_read_with_options(&book[..], &ParseOptions::new().max_file_size(100)).is_err());
+        assert!(limited(ParseOptions::new().max_record_count(2)).is_err());
+        assert!(limited(ParseOptions::new().max_exth_record_size(5)).is_err());
+
+        // second record decompresses to 11 bytes, over the record size, which
+        // is still accepted by default
+        let m = Mobi::new(&book).unwrap();
+        assert_eq!(m.content().unwrap(), b"abcaaaaaaaaaaa");
+        let m = limited(ParseOptions::new().max_record_size(10)).unwrap();
+        assert!(m.content().is_err());
+        assert!(m.text_record(1).is_ok());
+        let m = limited(ParseOptions::new().max_record_size(11)).unwrap();
+        assert_eq!(m.content().unwrap(), b"abcaaaaaaaaaaa");
+        let m = limited(ParseOptions::new().max_record_size(11).max_decompressed_size(10)).unwrap();
+        assert!(m.content().is_err());
+        assert!(m.text_reader().read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn reports_diagnostics() {
+        let record0 = book::Record0 {
+            record_count: 2,
+            compression: 2,
+            ..Default::default()
+        };
+        let records = [record0.build(), b"abc".to_vec(), b"c\x80\x20".to_vec()];
+        let book = book::pdb(&records, 2);
+        let m = Mobi::new(&book).unwrap();
+        assert!(m.metadata.diagnostics.is_empty());
+
+        let diagnostics = m.diagnostics().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.kind, DiagnosticKind::InvalidBackReference);
+        assert_eq!(diagnostic.record, Some(2));
+        assert_eq!(diagnostic.offset, Some(u64::from(m.metadata.records.records[2].0)));
+
+        let mut book = book;
+        let len = book.len() as u32;
+        book[78 + 8 * 2..78 + 8 * 2 + 4].copy_from_slice(&(len + 10).to_be_bytes());
+        let m = Mobi::new(&book).unwrap();
+        assert!(m.metadata.diagnostics.contains(DiagnosticKind::RecordOutOfBounds));
+        assert!(m.diagnostics().unwrap().contains(DiagnosticKind::UnreadableRecord));
     }
 }
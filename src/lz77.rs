@@ -1,63 +1,201 @@
-pub fn decompress_lz77(data: &[u8]) -> Vec<u8> {
-    let length = data.len();
-    let mut offset: usize = 0;
-    let mut text: Vec<u8> = vec![];
-    while offset < length {
+use crate::DiagnosticKind;
+use std::io;
+
+/// Decompresses PalmDOC LZ77 compressed `data`, appending the result to `out`.
+///
+/// Back references only reach into output produced by this call, so `out` may
+/// already hold content of preceding records. Text records decompress to at most
+/// `record_size` bytes stated in the PalmDOC header, so reserving that much
+/// capacity up front avoids any reallocation. Decompression stops with an error
+/// at the first invalid back reference or literal run cut short by the end of
+/// `data`, leaving content decompressed so far in `out`.
+pub fn decompress(data: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+    decompress_with(data, out, |_, e| Err(e))
+}
+
+/// Decompresses `data` like `decompress`, passing errors of invalid back references
+/// and truncated literal runs to `invalid` along with their kind. Decompression goes
+/// on past the invalid back reference, which is skipped, unless `invalid` returns
+/// an error. A truncated literal run keeps the bytes it holds.
+pub(crate) fn decompress_with(
+    data: &[u8],
+    out: &mut Vec<u8>,
+    mut invalid: impl FnMut(DiagnosticKind, io::Error) -> io::Result<()>,
+) -> io::Result<()> {
+    let base = out.len();
+    let mut offset = 0;
+    while offset < data.len() {
         let byte = data[offset];
         offset += 1;
         match byte {
             // The first character is a null which are literal
             // Chars from range 0x09..=0x7f are also literal
             0x0 | 0x09..=0x7f => {
-                text.push(byte);
+                let start = offset - 1;
+                while offset < data.len() && is_literal(data[offset]) {
+                    offset += 1;
+                }
+                out.extend_from_slice(&data[start..offset]);
             }
             // next $byte bytes are also literal
             0x1..=0x8 => {
-                if offset + byte as usize <= length {
-                    data[offset..(offset + byte as usize)].iter().for_each(|ch| {
-                        text.push(*ch);
-                    });
-                    offset += byte as usize;
+                let end = offset + byte as usize;
+                if end > data.len() {
+                    out.extend_from_slice(&data[offset..]);
+                    return invalid(DiagnosticKind::TruncatedLiteral, truncated_literal(offset - 1));
                 }
+                out.extend_from_slice(&data[offset..end]);
+                offset = end;
             }
             // Data is LZ77-compressed
             0x80..=0xbf => {
+                let next = match data.get(offset) {
+                    Some(&next) => next,
+                    None => return invalid(DiagnosticKind::InvalidBackReference, invalid_back_reference(offset - 1)),
+                };
                 offset += 1;
-                if offset > text.len() {
-                    return text;
-                }
-
-                let mut lz77 = u16::from_be_bytes([byte, text[offset - 1]]);
 
-                lz77 &= 0x3fff; // Leftmost two bits are ID bits and need to be dropped
-                let lz77length = (lz77 & 0x0007) + 3; // Length is  rightmost three bits + 3
-                let lz77offset = lz77 >> 3; // Remaining 11 bits are offset
-
-                if lz77offset < 1 {
-                    // Decompression offset is invalid?
-                    return text;
+                // Leftmost two bits are ID bits, next 11 bits are distance and
+                // rightmost three bits are length - 3
+                let pair = u16::from_be_bytes([byte, next]) & 0x3fff;
+                let distance = (pair >> 3) as usize;
+                let length = (pair & 0x7) as usize + 3;
+                if distance == 0 || distance > out.len() - base {
+                    invalid(DiagnosticKind::InvalidBackReference, invalid_back_reference(offset - 2))?;
+                    continue;
                 }
 
-                for _lz77pos in 0..lz77length {
-                    let mut textpos: usize = text.len();
-                    if textpos >= lz77offset as usize {
-                        textpos -= lz77offset as usize;
-                    } else {
-                        break;
-                    }
-                    let ch = &text[textpos..=textpos].to_owned();
-                    for c in ch {
-                        text.push(*c);
-                    }
+                // Overlapping match repeats bytes it has just produced, so it's
+                // copied in chunks of at most `distance` bytes
+                let start = out.len() - distance;
+                let end = out.len();
+                out.resize(end + length, 0);
+                let mut copied = 0;
+                while copied < length {
+                    let n = distance.min(length - copied);
+                    out.copy_within(start + copied..start + copied + n, end + copied);
+                    copied += n;
                 }
             }
             // 0xc0..= 0xff are single charaters XOR 0x80 preceded by a space
             _ => {
-                text.push(b' ');
-                text.push(byte ^ 0x80);
+                out.push(b' ');
+                out.push(byte ^ 0x80);
             }
         }
     }
+    Ok(())
+}
+
+fn invalid_back_reference(offset: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid LZ77 back reference at offset {}", offset),
+    )
+}
+
+fn truncated_literal(offset: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("LZ77 literal run at offset {} exceeds the data", offset),
+    )
+}
+
+fn is_literal(byte: u8) -> bool {
+    byte == 0 || (0x09..=0x7f).contains(&byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decompressed(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let _ = decompress(data, &mut out);
+        out
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(decompressed(b"abc\x00"), b"abc\x00");
+        assert_eq!(decompressed(b"\x03\x80\xc0\xffz"), b"\x80\xc0\xffz");
+        assert_eq!(decompressed(b"\xe8\xe9"), b" h i");
+    }
 
-    text
+    #[test]
+    fn truncated_literals() {
+        let mut out = Vec::new();
+        let e = decompress(b"ab\x05cd", &mut out).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(out, b"abcd");
+
+        let mut kinds = Vec::new();
+        let mut out = Vec::new();
+        decompress_with(b"ab\x05cd", &mut out, |kind, e| {
+            kinds.push((kind, e.to_string()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(out, b"abcd");
+        assert_eq!(
+            kinds,
+            vec![(
+                DiagnosticKind::TruncatedLiteral,
+                String::from("LZ77 literal run at offset 2 exceeds the data")
+            )]
+        );
+    }
+
+    #[test]
+    fn back_references() {
+        // distance 4, length 3
+        assert_eq!(decompressed(b"abcd\x80\x20"), b"abcdabc");
+        // distance 2, length 10 overlapping its own output
+        assert_eq!(decompressed(b"ab\x80\x17"), b"abababababab");
+        // distance 1, length 4
+        assert_eq!(decompressed(b"x\x80\x09"), b"xxxxx");
+    }
+
+    #[test]
+    fn invalid_back_references() {
+        let mut out = Vec::new();
+        let e = decompress(b"ab\x80\x20cd", &mut out).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(out, b"ab");
+        assert_eq!(decompressed(b"ab\x80\x20cd"), b"ab");
+        assert_eq!(decompressed(b"ab\x80\x00cd"), b"ab");
+        assert_eq!(decompressed(b"ab\x80"), b"ab");
+    }
+
+    #[test]
+    fn skips_invalid_back_references() {
+        let mut out = Vec::new();
+        let mut offsets = Vec::new();
+        decompress_with(b"ab\x80\x20cd\x80\x00e\x80\x11\x80", &mut out, |_, e| {
+            offsets.push(e.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(out, b"abcdedede");
+        assert_eq!(
+            offsets,
+            vec![
+                "invalid LZ77 back reference at offset 2",
+                "invalid LZ77 back reference at offset 6",
+                "invalid LZ77 back reference at offset 11",
+            ]
+        );
+    }
+
+    #[test]
+    fn appends_to_buffer() {
+        let mut out = b"abcd".to_vec();
+        decompress(b"x\x80\x08", &mut out).unwrap();
+        assert_eq!(out, b"abcdxxxx");
+
+        // back references don't reach into content preceding this call
+        let mut out = b"abcd".to_vec();
+        assert!(decompress(b"\x80\x20", &mut out).is_err());
+        assert_eq!(out, b"abcd");
+    }
 }
@@ -0,0 +1,324 @@
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Default cap on buffers allocated up front from lengths found in a book
+const DEFAULT_MAX_ALLOCATION: usize = 64 * 1024 * 1024;
+/// Default limit on size of a book file
+const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024 * 1024;
+/// Default limit on size of a single EXTH record
+const DEFAULT_MAX_EXTH_RECORD_SIZE: usize = 16 * 1024 * 1024;
+/// Default limit on size of the whole decompressed text
+const DEFAULT_MAX_DECOMPRESSED_SIZE: u64 = 512 * 1024 * 1024;
+/// Default limit on size of a single decompressed text record. Text records are
+/// meant to hold `record_size` bytes at most, which writers don't always stick
+/// to, so the limit is far above it.
+const DEFAULT_MAX_RECORD_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How inconsistencies found in a book are handled
+pub enum ParseMode {
+    /// Fail on the first inconsistency
+    Strict,
+    /// Recover from inconsistencies and keep going, skipping what can't be read
+    Lenient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How invalid sequences found while decoding text are handled
+pub enum DecodeErrors {
+    /// Return an error on the first invalid sequence
+    Fail,
+    /// Replace invalid sequences
+    Replace,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Error returned when a book exceeds one of the limits set with `ParseOptions`,
+/// wrapped in an `io::Error` of kind `InvalidData`. Such errors are never
+/// recovered from, regardless of the parse mode.
+pub struct LimitExceeded {
+    /// Name of the limited quantity
+    pub what: &'static str,
+    pub value: u64,
+    pub limit: u64,
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} exceeds the limit of {}", self.what, self.value, self.limit)
+    }
+}
+
+impl Error for LimitExceeded {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Options controlling how a book is parsed, built like:
+///
+/// ```no_run
+/// use mobi::{Mobi, ParseOptions};
+/// # fn main() -> std::io::Result<()> {
+/// let options = ParseOptions::new().strict().skip_exth(true);
+/// let m = Mobi::from_path_with_options("/some/path/to/book.mobi", &options)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// By default parsing is lenient, EXTH header and content are read and strict
+/// text decoding is used by `content_as_string`. Sizes are limited to values
+/// comfortably above those of real books, exceeding any of the limits is an
+/// error regardless of the parse mode.
+pub struct ParseOptions {
+    pub(crate) mode: ParseMode,
+    pub(crate) skip_exth: bool,
+    pub(crate) skip_content: bool,
+    pub(crate) max_allocation: usize,
+    pub(crate) decode_errors: DecodeErrors,
+    pub(crate) max_file_size: u64,
+    pub(crate) max_record_count: usize,
+    pub(crate) max_exth_record_size: usize,
+    pub(crate) max_record_size: usize,
+    pub(crate) max_decompressed_size: u64,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            mode: ParseMode::Lenient,
+            skip_exth: false,
+            skip_content: false,
+            max_allocation: DEFAULT_MAX_ALLOCATION,
+            decode_errors: DecodeErrors::Fail,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_record_count: usize::from(u16::MAX),
+            max_exth_record_size: DEFAULT_MAX_EXTH_RECORD_SIZE,
+            max_record_size: DEFAULT_MAX_RECORD_SIZE,
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Creates options for lenient parsing, reading EXTH header and content, failing
+    /// on invalid text sequences and limiting allocations up front to 64 MiB, file
+    /// size to 1 GiB, record count to 65535, EXTH records and decompressed text
+    /// records to 16 MiB each and the whole decompressed text to 512 MiB.
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    /// Sets how inconsistencies are handled
+    pub fn mode(mut self, mode: ParseMode) -> ParseOptions {
+        self.mode = mode;
+        self
+    }
+
+    /// Fail on the first inconsistency
+    pub fn strict(self) -> ParseOptions {
+        self.mode(ParseMode::Strict)
+    }
+
+    /// Recover from inconsistencies and keep going
+    pub fn lenient(self) -> ParseOptions {
+        self.mode(ParseMode::Lenient)
+    }
+
+    /// Don't parse the EXTH header. As it holds the location of the KF8 part of
+    /// joint MOBI/KF8 files, KF8 headers are skipped too.
+    pub fn skip_exth(mut self, skip: bool) -> ParseOptions {
+        self.skip_exth = skip;
+        self
+    }
+
+    /// Read only headers, leaving out content of records
+    pub fn skip_content(mut self, skip: bool) -> ParseOptions {
+        self.skip_content = skip;
+        self
+    }
+
+    /// Caps the size of buffers allocated up front from lengths found in a book.
+    /// Buffers still grow past it as content is actually read.
+    pub fn max_allocation(mut self, bytes: usize) -> ParseOptions {
+        self.max_allocation = bytes;
+        self
+    }
+
+    /// Sets how invalid sequences are handled by `content_as_string`
+    pub fn decode_errors(mut self, decode_errors: DecodeErrors) -> ParseOptions {
+        self.decode_errors = decode_errors;
+        self
+    }
+
+    /// Limits size of a book file read as a whole. Defaults to 1 GiB.
+    pub fn max_file_size(mut self, bytes: u64) -> ParseOptions {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Limits number of PDB records. Defaults to 65535, the most the format allows.
+    pub fn max_record_count(mut self, count: usize) -> ParseOptions {
+        self.max_record_count = count;
+        self
+    }
+
+    /// Limits size of a single EXTH record. Defaults to 16 MiB.
+    pub fn max_exth_record_size(mut self, bytes: usize) -> ParseOptions {
+        self.max_exth_record_size = bytes;
+        self
+    }
+
+    /// Limits size of a single decompressed text record. Defaults to 16 MiB.
+    pub fn max_record_size(mut self, bytes: usize) -> ParseOptions {
+        self.max_record_size = bytes;
+        self
+    }
+
+    /// Limits size of the whole decompressed text, as well as of all files
+    /// unpacked from archives embedded in a book. Defaults to 512 MiB.
+    pub fn max_decompressed_size(mut self, bytes: u64) -> ParseOptions {
+        self.max_decompressed_size = bytes;
+        self
+    }
+
+    pub(crate) fn check_file_size(&self, size: u64) -> io::Result<()> {
+        limit("file size", size, self.max_file_size)
+    }
+
+    pub(crate) fn check_record_count(&self, count: usize) -> io::Result<()> {
+        limit("record count", count as u64, self.max_record_count as u64)
+    }
+
+    pub(crate) fn check_exth_record_size(&self, size: usize) -> io::Result<()> {
+        limit("EXTH record size", size as u64, self.max_exth_record_size as u64)
+    }
+
+    pub(crate) fn check_record_size(&self, size: usize) -> io::Result<()> {
+        limit("decompressed record size", size as u64, self.max_record_size as u64)
+    }
+
+    pub(crate) fn check_decompressed_size(&self, size: u64) -> io::Result<()> {
+        limit("decompressed text size", size, self.max_decompressed_size)
+    }
+
+    #[cfg(feature = "deflate")]
+    pub(crate) fn check_unpacked_size(&self, size: u64) -> io::Result<()> {
+        limit("unpacked size", size, self.max_decompressed_size)
+    }
+
+    pub(crate) fn is_strict(&self) -> bool {
+        self.mode == ParseMode::Strict
+    }
+
+    /// Clamps a capacity taken from a book to the allocation cap
+    pub(crate) fn capacity(&self, len: usize) -> usize {
+        len.min(self.max_allocation)
+    }
+
+    /// Handles an inconsistency, returning an error in strict mode and recording
+    /// it in `diagnostics` in lenient mode
+    pub(crate) fn check(
+        &self,
+        diagnostics: &mut Diagnostics,
+        consistent: bool,
+        diagnostic: impl FnOnce() -> Diagnostic,
+    ) -> io::Result<()> {
+        if consistent {
+            return Ok(());
+        }
+        let diagnostic = diagnostic();
+        if self.is_strict() {
+            return Err(diagnostic.into());
+        }
+        diagnostics.push(diagnostic);
+        Ok(())
+    }
+
+    /// Handles a failure, replacing it with `fallback` and recording it in
+    /// `diagnostics` in lenient mode
+    pub(crate) fn recover<T>(
+        &self,
+        diagnostics: &mut Diagnostics,
+        result: io::Result<T>,
+        diagnostic: impl FnOnce(&io::Error) -> Diagnostic,
+        fallback: impl FnOnce() -> T,
+    ) -> io::Result<T> {
+        match result {
+            Err(ref e) if !self.is_strict() && !is_limit(e) => {
+                diagnostics.push(diagnostic(e));
+                Ok(fallback())
+            }
+            result => result,
+        }
+    }
+}
+
+fn limit(what: &'static str, value: u64, limit: u64) -> io::Result<()> {
+    if value > limit {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            LimitExceeded { what, value, limit },
+        ));
+    }
+    Ok(())
+}
+
+fn is_limit(e: &io::Error) -> bool {
+    match e.get_ref() {
+        Some(e) => e.is::<LimitExceeded>(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::DiagnosticKind;
+
+    #[test]
+    fn builds_options() {
+        let options = ParseOptions::new()
+            .strict()
+            .skip_exth(true)
+            .max_allocation(16)
+            .decode_errors(DecodeErrors::Replace);
+        assert!(options.is_strict());
+        assert!(options.skip_exth);
+        assert!(!options.skip_content);
+        assert_eq!(options.capacity(1024), 16);
+        assert_eq!(options.decode_errors, DecodeErrors::Replace);
+        let broken = || Diagnostic::new(DiagnosticKind::UnorderedRecords, "broken");
+        let mut diagnostics = Diagnostics::new();
+        assert!(options.check(&mut diagnostics, false, broken).is_err());
+        assert!(diagnostics.is_empty());
+
+        let options = options.lenient();
+        assert!(options.check(&mut diagnostics, true, broken).is_ok());
+        assert!(options.check(&mut diagnostics, false, broken).is_ok());
+        let result = Err(io::Error::from(io::ErrorKind::InvalidData));
+        assert_eq!(
+            options.recover(&mut diagnostics, result, |_| broken(), || 1).unwrap(),
+            1
+        );
+        assert_eq!(diagnostics.len(), 2);
+    }
+
+    #[test]
+    fn checks_limits() {
+        let options = ParseOptions::new();
+        assert!(options.check_record_size(DEFAULT_MAX_RECORD_SIZE).is_ok());
+        assert!(options.check_record_size(DEFAULT_MAX_RECORD_SIZE + 1).is_err());
+
+        let options = options.max_record_size(10).max_file_size(100);
+        assert!(options.check_record_size(10).is_ok());
+        assert!(options.check_record_size(11).is_err());
+        assert!(options.check_file_size(100).is_ok());
+        let error = options.check_file_size(101).unwrap_err();
+        assert_eq!(error.to_string(), "file size 101 exceeds the limit of 100");
+
+        let result = options.check_file_size(101).map(|_| 0);
+        assert!(options
+            .recover(&mut Diagnostics::new(), result, |_| unreachable!(), || 1)
+            .is_err());
+    }
+}
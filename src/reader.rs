@@ -1,16 +1,22 @@
-use byteorder::{BigEndian, ReadBytesExt};
-use std::io::{self, Cursor, Read};
+use crate::source::Source;
+use std::io;
+
+/// Number of bytes fetched from a source at once
+const CHUNK_LEN: usize = 4096;
 
 #[derive(Debug, Default)]
-/// Helper struct for reading header values from content
-pub(crate) struct Reader<'r> {
-    pub cursor: Cursor<&'r [u8]>,
+/// Helper struct for reading header values from a source, fetching its content
+/// in chunks
+pub(crate) struct Reader<S> {
+    source: S,
     pub num_records: u16,
+    position: u64,
+    chunk: Vec<u8>,
+    chunk_offset: u64,
+    chunk_len: usize,
 }
 
 pub(crate) trait MobiReader {
-    fn read_to_end(&mut self) -> io::Result<Vec<u8>>;
-
     fn set_num_records(&mut self, n: u16);
 
     fn get_num_records(&self) -> u16;
@@ -25,121 +31,51 @@ pub(crate) trait MobiReader {
 
     fn read_u8(&mut self) -> io::Result<u8>;
 
-    fn position_after_records(&self) -> u64;
-
     fn read_string_header(&mut self, start: u64, len: usize) -> io::Result<String>;
 }
 
-impl<'r> Reader<'r> {
-    pub(crate) fn new(content: &'r [u8]) -> Reader<'r> {
+impl<S: Source> Reader<S> {
+    pub(crate) fn new(source: S) -> Reader<S> {
         Reader {
-            cursor: Cursor::new(content),
+            source,
             num_records: 0,
+            position: 0,
+            chunk: Vec::new(),
+            chunk_offset: 0,
+            chunk_len: CHUNK_LEN,
         }
     }
-}
-
-impl<'r> MobiReader for Reader<'r> {
-    fn read_to_end(&mut self) -> io::Result<Vec<u8>> {
-        let mut first_buf = vec![0; self.cursor.position() as usize];
-        let mut second_buf = vec![];
-        self.cursor.read_to_end(&mut second_buf)?;
-        first_buf.extend_from_slice(&second_buf);
-        Ok(first_buf)
-    }
-
-    fn get_num_records(&self) -> u16 {
-        self.num_records
-    }
-
-    #[inline]
-    fn set_num_records(&mut self, n: u16) {
-        self.num_records = n;
-    }
-
-    #[inline]
-    fn set_position(&mut self, n: u64) {
-        debug_assert!(n >= self.cursor.position(), "{}, {}", n, self.cursor.position());
-        self.cursor.set_position(n);
-    }
-
-    #[inline]
-    fn read_u32_be(&mut self) -> io::Result<u32> {
-        self.cursor.read_u32::<BigEndian>()
-    }
-
-    #[inline]
-    fn read_u16_be(&mut self) -> io::Result<u16> {
-        self.cursor.read_u16::<BigEndian>()
-    }
-
-    #[inline]
-    fn read_u8(&mut self) -> io::Result<u8> {
-        self.cursor.read_u8()
-    }
-
-    fn position_after_records(&self) -> u64 {
-        self.num_records as u64 * 8
-    }
 
-    fn read_string_header(&mut self, start: u64, len: usize) -> io::Result<String> {
-        self.cursor.set_position(start);
-        let mut buf = vec![0; len];
-        self.cursor.read_exact(&mut buf)?;
-        Ok(String::from_utf8_lossy(&buf).to_owned().to_string())
-    }
-
-    fn get_position(&self) -> u64 {
-        self.cursor.position()
-    }
-}
-
-#[derive(Debug, Default)]
-/// Helper struct for reading header values from content
-pub(crate) struct ReaderPrime<R> {
-    pub reader: R,
-    pub num_records: u16,
-    position: usize,
-}
-
-impl<R: std::io::Read> ReaderPrime<R> {
-    pub(crate) fn new(content: R) -> ReaderPrime<R> {
-        ReaderPrime {
-            reader: content,
-            num_records: 0,
-            position: 0,
+    /// Creates a reader fetching only bytes that are read, for sources that
+    /// shouldn't be read past the end of headers
+    pub(crate) fn exact(source: S) -> Reader<S> {
+        Reader {
+            chunk_len: 0,
+            ..Reader::new(source)
         }
     }
 
-    // Will read from ?..p, so p itself will not be read, but p - 1 will exist.
-    fn read_to_point(&mut self, p: usize) -> io::Result<()> {
-        debug_assert!(p >= self.position, "{}, {}", p, self.position);
-
-        if p > self.position {
-            std::io::copy(
-                &mut self.reader.by_ref().take((p - self.position) as u64),
-                &mut io::sink(),
-            )?;
-            self.position = p;
+    fn read_bytes(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let (start, end) = (self.position, self.position + buf.len() as u64);
+        if start < self.chunk_offset || end > self.chunk_offset + self.chunk.len() as u64 {
+            self.chunk = self.source.read_at(start, buf.len().max(self.chunk_len))?;
+            self.chunk_offset = start;
+            if self.chunk.len() < buf.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ));
+            }
         }
 
+        let from = (start - self.chunk_offset) as usize;
+        buf.copy_from_slice(&self.chunk[from..from + buf.len()]);
+        self.position = end;
         Ok(())
     }
 }
 
-impl<R: std::io::Read> MobiReader for ReaderPrime<R> {
-    fn get_position(&self) -> u64 {
-        self.position as u64
-    }
-
-    fn read_to_end(&mut self) -> io::Result<Vec<u8>> {
-        let mut first_buf = vec![0; self.position];
-        let mut second_buf = vec![];
-        self.reader.read_to_end(&mut second_buf)?;
-        first_buf.extend_from_slice(&second_buf);
-        Ok(first_buf)
-    }
-
+impl<S: Source> MobiReader for Reader<S> {
     fn get_num_records(&self) -> u16 {
         self.num_records
     }
@@ -151,42 +87,66 @@ impl<R: std::io::Read> MobiReader for ReaderPrime<R> {
 
     #[inline]
     fn set_position(&mut self, n: u64) {
-        self.read_to_point(n as usize).unwrap();
+        self.position = n;
+    }
+
+    fn get_position(&self) -> u64 {
+        self.position
     }
 
     #[inline]
     fn read_u32_be(&mut self) -> io::Result<u32> {
         let mut bytes = [0; 4];
-        self.reader.read_exact(&mut bytes)?;
-        self.position += 4;
+        self.read_bytes(&mut bytes)?;
         Ok(u32::from_be_bytes(bytes))
     }
 
     #[inline]
     fn read_u16_be(&mut self) -> io::Result<u16> {
         let mut bytes = [0; 2];
-        self.reader.read_exact(&mut bytes)?;
-        self.position += 2;
+        self.read_bytes(&mut bytes)?;
         Ok(u16::from_be_bytes(bytes))
     }
 
     #[inline]
     fn read_u8(&mut self) -> io::Result<u8> {
-        self.position += 1;
-        self.reader.read_u8()
+        let mut bytes = [0; 1];
+        self.read_bytes(&mut bytes)?;
+        Ok(bytes[0])
     }
 
-    fn position_after_records(&self) -> u64 {
-        self.num_records as u64 * 8
+    fn read_string_header(&mut self, start: u64, len: usize) -> io::Result<String> {
+        // Read straight from the source, so that a length found in a book can't
+        // allocate more than the content actually holds
+        let buf = self.source.read_at(start, len)?;
+        if buf.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+        self.set_position(start + len as u64);
+        Ok(String::from_utf8_lossy(&buf).into_owned())
     }
+}
 
-    fn read_string_header(&mut self, start: u64, len: usize) -> io::Result<String> {
-        self.read_to_point(start as usize)?;
-        let mut buf = vec![0; len];
+/// Reads a big endian u32 at `offset` of `data`, if `data` is long enough to hold it
+pub(crate) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
 
-        self.reader.read_exact(&mut buf)?;
-        self.position += len;
+/// Reads a big endian u16 at `offset` of `data`, if `data` is long enough to hold it
+pub(crate) fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    data.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
 
-        Ok(String::from_utf8_lossy(&buf).to_owned().to_string())
-    }
+/// Overwrites a big endian u32 at `offset` of `buf`
+pub(crate) fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Overwrites a big endian u16 at `offset` of `buf`
+pub(crate) fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
 }
@@ -1,6 +1,9 @@
 use super::{lz77, TextEncoding};
-use crate::headers::palmdoch::Compression;
-use encoding::{all::WINDOWS_1252, DecoderTrap, Encoding};
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics};
+use crate::headers::palmdoch::{Compression, PalmDocHeader};
+use crate::headers::MobiMetadata;
+use crate::text::{TextDecoder, TextReader};
+use crate::{ParseOptions, Source};
 use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
@@ -31,7 +34,56 @@ pub struct Record {
     id: u32,
     pub record_data: Vec<u8>,
     pub length: usize,
+    // Bytes completing a multibyte character split between this record and the next one
+    overlap: Vec<u8>,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A raw PDB record, borrowed as is from the content of a book
+pub struct RawRecord<'a> {
+    /// Index of this record in the PDB record list
+    pub index: usize,
+    /// Offset of this record from the beginning of the file
+    pub offset: u32,
+    /// PDB attributes of this record
+    pub attributes: u8,
+    /// Unique id of this record
+    pub unique_id: u32,
+    /// Raw content of this record
+    pub data: &'a [u8],
+}
+
+impl<'a> RawRecord<'a> {
+    /// Builds a raw record out of its entry in the PDB record list
+    pub(crate) fn new(index: usize, (offset, id): (u32, u32), data: &'a [u8]) -> RawRecord<'a> {
+        RawRecord {
+            index,
+            offset,
+            attributes: attributes(id),
+            unique_id: unique_id(id),
+            data,
+        }
+    }
+}
+
+/// Error returned when text content of a record that doesn't hold text is requested
+pub(crate) fn not_text_record(index: usize) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("record {} is not a text record", index),
+    )
+}
+
+/// The second field of a PDB record list entry holds attributes in its
+/// highest byte and unique id in the three remaining ones.
+fn attributes(id: u32) -> u8 {
+    (id >> 24) as u8
+}
+
+fn unique_id(id: u32) -> u32 {
+    id & 0x00FF_FFFF
+}
+
 impl Record {
     #[allow(dead_code)]
     fn new() -> Record {
@@ -40,95 +92,541 @@ impl Record {
             id: 0,
             record_data: Vec::new(),
             length: 0,
+            overlap: Vec::new(),
         }
     }
 
-    /// Reads the content of a record at specified offset
-    fn record_data(
-        record_data_offset: u32,
-        next_record_data_offset: u32,
-        extra_bytes: u32,
-        compression_type: &Compression,
-        content: &[u8],
+    /// Returns offset of this record from the beginning of the file
+    pub fn offset(&self) -> u32 {
+        self.record_data_offset
+    }
+
+    /// Returns PDB attributes of this record
+    pub fn attributes(&self) -> u8 {
+        attributes(self.id)
+    }
+
+    /// Returns unique id of this record
+    pub fn unique_id(&self) -> u32 {
+        unique_id(self.id)
+    }
+
+    /// Strips trailing entries from raw content of a text record located at
+    /// `(index, offset)` and decompresses it
+    pub(crate) fn decompress(
+        data: &[u8],
+        (index, offset): (usize, u32),
+        extra_data_flags: u16,
+        palmdoc: &PalmDocHeader,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
     ) -> io::Result<Vec<u8>> {
+        let diagnostic = |kind, message: String| Diagnostic::new(kind, message).record(index).offset(u64::from(offset));
+
+        let (size, overflow) = Record::trailing_entries(data, extra_data_flags);
+        options.check(diagnostics, !overflow, || {
+            diagnostic(
+                DiagnosticKind::InvalidTrailingEntries,
+                String::from("trailing entries exceed size of the record"),
+            )
+        })?;
+        let data = &data[..data.len() - size];
+
         // #TODO: reconsider using string here due to possible different encodings?
-        match compression_type {
-            Compression::No => Ok(content[record_data_offset as usize..next_record_data_offset as usize].to_vec()),
+        let text = match palmdoc.compression() {
+            Compression::No => data.to_vec(),
             Compression::PalmDoc => {
-                if record_data_offset < content.len() as u32
-                    && record_data_offset < next_record_data_offset - extra_bytes
-                {
-                    Ok(lz77::decompress_lz77(
-                        &content[record_data_offset as usize..(next_record_data_offset - extra_bytes) as usize],
-                    ))
-                } else {
-                    Err(io::Error::new(
-                        ErrorKind::NotFound,
-                        "record points to location out of bounds",
-                    ))
-                }
+                let mut text = Vec::with_capacity(options.capacity(palmdoc.record_size as usize));
+                lz77::decompress_with(data, &mut text, |kind, e| {
+                    options.recover(diagnostics, Err(e), |e| diagnostic(kind, e.to_string()), || ())
+                })?;
+                text
             }
-            Compression::Huff => panic!("Huff compression is currently not supported"),
-        }
+            Compression::Huff => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Huff/CDIC compression is not supported",
+                ))
+            }
+            Compression::Unknown(n) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unknown compression type {}", n),
+                ))
+            }
+        };
+
+        // LZ77 expands data at most fivefold, so the record is checked only once decompressed
+        options.check_record_size(text.len())?;
+        Ok(text)
     }
 
-    /// Gets all records in the specified content
-    pub(crate) fn parse_records(
-        content: &[u8],
-        record_info: &[(u32, u32)],
-        _extra_bytes: u32,
-        compression_type: Compression,
-    ) -> io::Result<Vec<Record>> {
-        let mut new_records = vec![];
-        for records in record_info.windows(2) {
-            let (curr_offset, id) = records[0];
-            let (next_offset, _) = records[1];
-            let record_data = if _extra_bytes < next_offset {
-                match Record::record_data(curr_offset, next_offset, _extra_bytes, &compression_type, content) {
-                    Ok(record) => record,
-                    Err(_) => Vec::new(),
+    /// Builds a record out of its index, entry in the PDB record list and raw content
+    pub(crate) fn from_raw(
+        index: usize,
+        (record_data_offset, id): (u32, u32),
+        data: &[u8],
+        extra_data_flags: u16,
+        palmdoc: &PalmDocHeader,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Record> {
+        let record_data = Record::decompress(
+            data,
+            (index, record_data_offset),
+            extra_data_flags,
+            palmdoc,
+            options,
+            diagnostics,
+        )?;
+        Ok(Record {
+            record_data_offset,
+            id,
+            length: record_data.len(),
+            record_data,
+            overlap: Record::multibyte_overlap(data, extra_data_flags).to_vec(),
+        })
+    }
+
+    /// Returns the size of trailing entries appended at the end of a text record.
+    /// Every set bit of `extra_data_flags` except the lowest one marks an entry
+    /// ending with its own size, while the lowest bit marks an entry holding
+    /// bytes of a multibyte character overlapping the next record.
+    pub(crate) fn trailing_entries_size(data: &[u8], extra_data_flags: u16) -> usize {
+        Record::trailing_entries(data, extra_data_flags).0
+    }
+
+    /// Returns the size of trailing entries clamped to the size of the data, along
+    /// with whether they claim to be larger than that.
+    fn trailing_entries(data: &[u8], extra_data_flags: u16) -> (usize, bool) {
+        let mut size = 0;
+        let mut flags = extra_data_flags >> 1;
+
+        while flags != 0 {
+            if flags & 1 != 0 {
+                size += Record::trailing_entry_size(&data[..data.len() - size]);
+                if size > data.len() {
+                    return (data.len(), true);
                 }
-            } else {
-                Vec::new()
-            };
+            }
+            flags >>= 1;
+        }
 
-            let record = Record {
-                record_data_offset: curr_offset,
-                id,
-                length: record_data.len(),
-                record_data,
-            };
+        if extra_data_flags & 1 != 0 {
+            if size == data.len() {
+                return (size, true);
+            }
+            size += (data[data.len() - size - 1] & 0x3) as usize + 1;
+        }
+
+        (size.min(data.len()), size > data.len())
+    }
 
-            new_records.push(record);
+    /// Returns bytes held by the multibyte trailing entry, which repeat the
+    /// beginning of the next record up to the end of a character split between
+    /// the two records.
+    fn multibyte_overlap(data: &[u8], extra_data_flags: u16) -> &[u8] {
+        if extra_data_flags & 1 == 0 {
+            return &[];
         }
+        let end = data.len() - Record::trailing_entries_size(data, extra_data_flags & !1);
+        match end.checked_sub(1) {
+            Some(size_pos) => {
+                let len = (data[size_pos] & 0x3) as usize;
+                &data[size_pos.saturating_sub(len)..size_pos]
+            }
+            None => &[],
+        }
+    }
+
+    /// Decodes the backward encoded size of the trailing entry ending the data.
+    fn trailing_entry_size(data: &[u8]) -> usize {
+        let mut size = 0;
+        for (bitpos, &byte) in data.iter().rev().take(4).enumerate() {
+            size |= ((byte & 0x7F) as usize) << (7 * bitpos);
+            if byte & 0x80 != 0 {
+                break;
+            }
+        }
+        size
+    }
 
-        if let Some(&(record_data_offset, id)) = record_info.last() {
-            new_records.push(Record {
+    /// Falls back to an empty record if the text record located at `index`
+    /// couldn't be read, unless parsing is strict
+    pub(crate) fn recover(
+        index: usize,
+        (record_data_offset, id): (u32, u32),
+        record: io::Result<Record>,
+        options: &ParseOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Record> {
+        options.recover(
+            diagnostics,
+            record,
+            |e| {
+                Diagnostic::new(DiagnosticKind::UnreadableRecord, e.to_string())
+                    .record(index)
+                    .offset(u64::from(record_data_offset))
+            },
+            || Record {
                 record_data_offset,
                 id,
                 record_data: vec![],
                 length: 0,
-            });
+                overlap: vec![],
+            },
+        )
+    }
+
+    /// Returns content of this record taken in isolation, ending with the whole
+    /// character split with the next record and without bytes of a character
+    /// started in the previous one.
+    fn text_bytes(&self, encoding: TextEncoding) -> Cow<'_, [u8]> {
+        match encoding {
+            TextEncoding::UTF8 => {
+                let start = self
+                    .record_data
+                    .iter()
+                    .take(3)
+                    .take_while(|&&b| b & 0xC0 == 0x80)
+                    .count();
+                if self.overlap.is_empty() {
+                    Cow::Borrowed(&self.record_data[start..])
+                } else {
+                    let mut data = self.record_data[start..].to_vec();
+                    data.extend_from_slice(&self.overlap);
+                    Cow::Owned(data)
+                }
+            }
+            TextEncoding::CP1252 => Cow::Borrowed(&self.record_data),
         }
+    }
 
-        Ok(new_records)
+    /// Decodes content of this record, replacing invalid sequences. Characters
+    /// split between records are attributed to the record they begin in.
+    pub fn to_string_lossy(&self, encoding: TextEncoding) -> String {
+        let mut text = String::new();
+        let mut decoder = TextDecoder::new(encoding);
+        decoder.decode_lossy(&self.text_bytes(encoding), &mut text);
+        decoder.finish_lossy(&mut text);
+        text
     }
 
-    pub(crate) fn to_string_lossy(&self, encoding: TextEncoding) -> String {
-        match encoding {
-            TextEncoding::UTF8 => String::from_utf8_lossy(&self.record_data).to_owned().to_string(),
-            TextEncoding::CP1252 => WINDOWS_1252.decode(&self.record_data, DecoderTrap::Ignore).unwrap(),
+    /// Decodes content of this record, returning error on invalid sequences.
+    /// Characters split between records are attributed to the record they begin in.
+    pub fn to_string(&self, encoding: TextEncoding) -> Result<String, DecodeError> {
+        let mut text = String::new();
+        let mut decoder = TextDecoder::new(encoding);
+        decoder.decode(&self.text_bytes(encoding), &mut text)?;
+        decoder.finish()?;
+        Ok(text)
+    }
+}
+
+/// Reader of text records out of any source of book content, shared by `Mobi`,
+/// `LazyMobi` and `AsyncLazyMobi` so that they read them the same way
+#[derive(Clone, Copy)]
+pub(crate) struct TextRecords<'m> {
+    metadata: &'m MobiMetadata,
+    options: &'m ParseOptions,
+    /// Length of the whole content
+    len: u64,
+}
+
+impl<'m> TextRecords<'m> {
+    pub(crate) fn new(metadata: &'m MobiMetadata, options: &'m ParseOptions, len: u64) -> TextRecords<'m> {
+        TextRecords { metadata, options, len }
+    }
+
+    /// Reads raw content of the record at `index`, or returns `None` if it lies
+    /// outside of content
+    fn raw(&self, source: &mut impl Source, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let records = &self.metadata.records.records;
+        let (start, _) = *records
+            .get(index)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("record {} does not exist", index)))?;
+        let start = u64::from(start);
+        let end = records
+            .get(index + 1)
+            .map_or(self.len, |&(offset, _)| u64::from(offset));
+        if start > end || end > self.len {
+            return Ok(None);
+        }
+        let data = source.read_at(start, (end - start) as usize)?;
+        if (data.len() as u64) < end - start {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
         }
+        Ok(Some(data))
     }
 
-    pub(crate) fn to_string(&self, encoding: TextEncoding) -> Result<String, DecodeError> {
-        match encoding {
-            TextEncoding::UTF8 => {
-                String::from_utf8(self.record_data.clone()).map_err(|e| DecodeError::UTF8(e.to_string()))
-            }
-            TextEncoding::CP1252 => WINDOWS_1252
-                .decode(&self.record_data, DecoderTrap::Strict)
-                .map_err(DecodeError::CP1252),
+    fn decompress(&self, index: usize, data: Option<Vec<u8>>, diagnostics: &mut Diagnostics) -> io::Result<Record> {
+        let data =
+            data.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "record points to location out of bounds"))?;
+        let (palmdoc, mobi) = self.metadata.text_headers(index);
+        Record::from_raw(
+            index,
+            self.metadata.records.records[index],
+            &data,
+            mobi.extra_data_flags,
+            palmdoc,
+            self.options,
+            diagnostics,
+        )
+    }
+
+    /// Reads and decompresses the text record at `index`
+    pub(crate) fn read(
+        &self,
+        source: &mut impl Source,
+        index: usize,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Record> {
+        let data = self.raw(source, index)?;
+        self.decompress(index, data, diagnostics)
+    }
+
+    /// Reads the text record at `index` like `TextRecords::read`, ending up with
+    /// an empty record if it lies outside of content or can't be decompressed,
+    /// unless parsing is strict. Errors of the source itself are returned as is.
+    pub(crate) fn recover(
+        &self,
+        source: &mut impl Source,
+        index: usize,
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Record> {
+        let data = self.raw(source, index)?;
+        let record = self.decompress(index, data, diagnostics);
+        Record::recover(
+            index,
+            self.metadata.records.records[index],
+            record,
+            self.options,
+            diagnostics,
+        )
+    }
+
+    /// Reads text records at `indexes` one after another, recovering them like
+    /// `TextRecords::recover`
+    pub(crate) fn read_all(
+        &self,
+        source: &mut impl Source,
+        indexes: &[usize],
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Vec<Record>> {
+        let mut records = Vec::with_capacity(indexes.len());
+        let mut total = 0;
+        for &i in indexes {
+            let record = self.recover(source, i, diagnostics)?;
+            total += record.record_data.len() as u64;
+            self.options.check_decompressed_size(total)?;
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Reads text records at `indexes` out of the whole `content` like
+    /// `TextRecords::read_all`, in parallel if `rayon` is enabled
+    pub(crate) fn read_content(
+        &self,
+        content: &[u8],
+        indexes: &[usize],
+        diagnostics: &mut Diagnostics,
+    ) -> io::Result<Vec<Record>> {
+        let read = |&i: &usize| {
+            let mut found = Diagnostics::new();
+            let record = self.recover(&mut &content[..], i, &mut found)?;
+            Ok((record, found))
+        };
+
+        // Records are independent of each other, so with `rayon` enabled they are
+        // decompressed in parallel, still ending up in the order of `indexes`.
+        #[cfg(feature = "rayon")]
+        let new_records = {
+            use rayon::prelude::*;
+            let new_records: io::Result<Vec<(Record, Diagnostics)>> = indexes.par_iter().map(read).collect();
+            new_records
+        };
+        #[cfg(not(feature = "rayon"))]
+        let new_records: io::Result<Vec<(Record, Diagnostics)>> = indexes.iter().map(read).collect();
+
+        let mut records = Vec::with_capacity(indexes.len());
+        let mut total = 0;
+        for (record, found) in new_records? {
+            total += record.record_data.len() as u64;
+            records.push(record);
+            diagnostics.extend(found);
         }
+        self.options.check_decompressed_size(total)?;
+        Ok(records)
+    }
+
+    /// Returns a reader over text records at `indexes`, recovering them like
+    /// `TextRecords::recover` while it is being read
+    pub(crate) fn text_reader<S: Source + 'm>(self, mut source: S, indexes: Vec<usize>) -> TextReader<'m> {
+        let mut total = 0;
+        TextReader::new(indexes, move |i| {
+            let data = self.recover(&mut source, i, &mut Diagnostics::new())?.record_data;
+            total += data.len() as u64;
+            self.options.check_decompressed_size(total)?;
+            Ok(data)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_entries_size() {
+        let data = b"hello\xAA\x82";
+        assert_eq!(Record::trailing_entries_size(data, 0), 0);
+        assert_eq!(Record::trailing_entries_size(data, 0b10), 2);
+
+        let data = b"helloX\x01\xAA\x82";
+        assert_eq!(Record::trailing_entries_size(data, 0b11), 4);
+
+        let data = b"hello\x81\x02\x82\x81";
+        assert_eq!(Record::trailing_entries_size(data, 0b1110), 4);
+    }
+
+    #[test]
+    fn trailing_entries_size_out_of_bounds() {
+        assert_eq!(Record::trailing_entries_size(b"\x8F", 0b10), 1);
+        assert_eq!(Record::trailing_entries_size(b"", 0b11), 0);
+    }
+
+    #[test]
+    fn strict_decompression() {
+        let palmdoc = PalmDocHeader {
+            compression: 2,
+            ..Default::default()
+        };
+        let (strict, lenient) = (ParseOptions::new().strict(), ParseOptions::new());
+        let mut diagnostics = Diagnostics::new();
+        let mut decompress = |data: &[u8], flags, palmdoc, options| {
+            Record::decompress(data, (1, 10), flags, palmdoc, options, &mut diagnostics)
+        };
+
+        // back reference pointing before the beginning of the record, skipped leniently
+        let data = b"ab\x80\x20cd";
+        assert!(decompress(data, 0, &palmdoc, &strict).is_err());
+        assert_eq!(decompress(data, 0, &palmdoc, &lenient).unwrap(), b"abcd");
+
+        // literal run cut short by the end of the record, kept leniently
+        let data = b"ab\x05cd";
+        assert!(decompress(data, 0, &palmdoc, &strict).is_err());
+        assert_eq!(decompress(data, 0, &palmdoc, &lenient).unwrap(), b"abcd");
+
+        // trailing entry larger than the record
+        let data = b"ab\x8F";
+        assert!(decompress(data, 0b10, &palmdoc, &strict).is_err());
+        assert_eq!(decompress(data, 0b10, &palmdoc, &lenient).unwrap(), b"");
+
+        let huff = PalmDocHeader {
+            compression: 17480,
+            ..Default::default()
+        };
+        assert!(decompress(b"ab", 0, &huff, &lenient).is_err());
+
+        let kinds: Vec<_> = diagnostics.iter().map(|d| (d.kind, d.record, d.offset)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (DiagnosticKind::InvalidBackReference, Some(1), Some(10)),
+                (DiagnosticKind::TruncatedLiteral, Some(1), Some(10)),
+                (DiagnosticKind::InvalidTrailingEntries, Some(1), Some(10)),
+            ]
+        );
+
+        // record 2 ends before it starts
+        let mut metadata = MobiMetadata::default();
+        metadata.palmdoc.compression = 2;
+        metadata.records.records = vec![(0, 0), (6, 2), (8, 4), (7, 6)];
+        let content = b"ab\x80\x20cdef";
+        let text = |options| TextRecords::new(&metadata, options, content.len() as u64);
+        let mut diagnostics = Diagnostics::new();
+        assert!(text(&strict).read_content(content, &[0, 1], &mut diagnostics).is_err());
+        assert!(text(&strict)
+            .read_all(&mut &content[..], &[1, 2], &mut diagnostics)
+            .is_err());
+        let records = text(&lenient)
+            .read_content(content, &[0, 1, 2], &mut diagnostics)
+            .unwrap();
+        assert_eq!(records[0].record_data, b"abcd");
+        assert_eq!(records[1].record_data, b"ef");
+        assert_eq!(records[2].record_data, b"");
+        let kinds: Vec<_> = diagnostics.iter().map(|d| (d.kind, d.record)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (DiagnosticKind::InvalidBackReference, Some(0)),
+                (DiagnosticKind::UnreadableRecord, Some(2)),
+            ]
+        );
+
+        let mut diagnostics = Diagnostics::new();
+        let sequential = text(&lenient)
+            .read_all(&mut &content[..], &[0, 1, 2], &mut diagnostics)
+            .unwrap();
+        let data: Vec<_> = sequential.iter().map(|r| r.record_data.clone()).collect();
+        assert_eq!(data, vec![b"abcd".to_vec(), b"ef".to_vec(), vec![]]);
+        assert_eq!(diagnostics.len(), 2);
+    }
+
+    #[test]
+    fn read_content_keeps_order() {
+        let mut metadata = MobiMetadata::default();
+        metadata.palmdoc.compression = 2;
+        let mut content = Vec::new();
+        for i in 0..64u32 {
+            metadata.records.records.push((content.len() as u32, i));
+            content.extend_from_slice(format!("\x04{:04}", i).as_bytes());
+        }
+        let indexes: Vec<usize> = (1..64).rev().collect();
+
+        let options = ParseOptions::default();
+        let records = TextRecords::new(&metadata, &options, content.len() as u64)
+            .read_content(&content, &indexes, &mut Diagnostics::new())
+            .unwrap();
+        for (record, &i) in records.iter().zip(&indexes) {
+            assert_eq!(record.record_data, format!("{:04}", i).as_bytes());
+            assert_eq!(record.unique_id(), i as u32);
+        }
+    }
+
+    #[test]
+    fn decodes_characters_split_between_records() {
+        // "zażółć" split in the middle of "ó", with the multibyte entry holding
+        // its remaining byte and the trailing entry marking its own size
+        let palmdoc = PalmDocHeader {
+            compression: 1,
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let mut diagnostics = Diagnostics::new();
+        let first = Record::from_raw(
+            0,
+            (0, 0),
+            b"za\xc5\xbc\xc3\xb3\x01",
+            0b1,
+            &palmdoc,
+            &options,
+            &mut diagnostics,
+        )
+        .unwrap();
+        let second = Record::from_raw(
+            0,
+            (0, 0),
+            b"\xb3\xc5\x82\xc4\x87\x00",
+            0b1,
+            &palmdoc,
+            &options,
+            &mut diagnostics,
+        )
+        .unwrap();
+        assert_eq!(first.record_data, b"za\xc5\xbc\xc3");
+        assert_eq!(first.to_string(TextEncoding::UTF8).unwrap(), "zażó");
+        assert_eq!(second.to_string(TextEncoding::UTF8).unwrap(), "łć");
     }
 }
@@ -0,0 +1,516 @@
+use crate::diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, HeaderKind};
+use crate::headers::mobih::offsets as mobi;
+use crate::headers::palmdoch::{offsets as palmdoc, PALMDOC_HEADER_LEN};
+use crate::headers::{ExthRecord, TextEncoding, NULL_INDEX, PDB_HEADER_LEN};
+use crate::reader::{put_u16, put_u32, u32_at};
+use crate::record::Record;
+use crate::trailer::{Fcis, Flis, EOF_RECORD};
+use crate::{kind, Mobi, RecordKind};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+
+/// Size of text records written to repaired books
+const RECORD_SIZE: usize = 4096;
+const EXTH_ON_FLAG: u32 = 0x40;
+
+#[derive(Debug, Clone, Default)]
+/// Content salvaged from a possibly damaged book by `Mobi::recover`, which can
+/// be written out as a repaired book.
+pub struct Recovered {
+    /// Decompressed content of every readable text record, in order
+    pub text: Vec<u8>,
+    /// Every readable image record, in order
+    pub images: Vec<Vec<u8>>,
+    /// Records that had to be skipped or truncated and header counts that had to be fixed
+    pub diagnostics: Diagnostics,
+    pdb_name: Vec<u8>,
+    created: u32,
+    modified: u32,
+    /// MOBI header as found in the book, without PalmDOC header and EXTH header
+    mobi_header: Vec<u8>,
+    exth: Vec<(u32, Vec<u8>)>,
+    name: String,
+    encoding: Option<TextEncoding>,
+}
+
+impl<'a> Mobi<'a> {
+    /// Salvages every readable text and image record of the MOBI part of the book.
+    /// Corrupt records are skipped, truncated ones are read up to the end of the
+    /// file and header counts exceeding records of the book are fixed, all of it
+    /// being listed in diagnostics of the result. Fails on exceeded limits and on
+    /// KF8-only books, whose FDST, skeleton and fragment indexes can't be rebuilt.
+    pub fn recover(&self) -> io::Result<Recovered> {
+        let metadata = &self.metadata;
+        if metadata.mobi.is_kf8() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "KF8-only books can't be repaired, as their FDST, skeleton and fragment indexes aren't rebuilt",
+            ));
+        }
+        let mut diagnostics = metadata.diagnostics.clone();
+        let count = self.record_count();
+        let end = metadata
+            .kf8
+            .as_ref()
+            .map_or(count, |kf8| (kf8.record0 as usize).saturating_sub(1).min(count));
+
+        // Text records have to precede the first image record
+        let first_image = metadata.mobi.first_image_index;
+        let text_end = if first_image == NULL_INDEX {
+            end
+        } else {
+            end.min(first_image as usize)
+        };
+        let record_count = usize::from(metadata.palmdoc.record_count);
+        if record_count >= text_end {
+            diagnostics.push(
+                Diagnostic::new(
+                    DiagnosticKind::InvalidRecordCount,
+                    format!(
+                        "text record count {} exceeds {} available records",
+                        record_count,
+                        text_end.saturating_sub(1)
+                    ),
+                )
+                .header(HeaderKind::PalmDoc)
+                .record(0),
+            );
+        }
+
+        let mut text = Vec::new();
+        let options = &self.options;
+        for i in 1..=record_count.min(text_end.saturating_sub(1)) {
+            let data = match self.recover_record(i, &mut diagnostics) {
+                Some(data) => data,
+                None => continue,
+            };
+            let entry = metadata.records.records[i];
+            let palmdoc = &metadata.palmdoc;
+            let record = Record::from_raw(
+                i,
+                entry,
+                data,
+                metadata.mobi.extra_data_flags,
+                palmdoc,
+                options,
+                &mut diagnostics,
+            );
+            match options.clone().lenient().recover(
+                &mut diagnostics,
+                record.map(Some),
+                |e| {
+                    Diagnostic::new(DiagnosticKind::UnreadableRecord, e.to_string())
+                        .record(i)
+                        .offset(u64::from(entry.0))
+                },
+                || None,
+            )? {
+                Some(record) => text.extend_from_slice(&record.record_data),
+                None => continue,
+            }
+            options.check_decompressed_size(text.len() as u64)?;
+        }
+
+        // Indexes of images relative to the first one, before and after recovery
+        let mut images = Vec::new();
+        let mut image_indexes = HashMap::new();
+        if first_image != NULL_INDEX {
+            for i in (first_image as usize)..end {
+                let data = match self.recover_record(i, &mut diagnostics) {
+                    Some(data) => data,
+                    None => continue,
+                };
+                if RecordKind::from_magic(&data[..data.len().min(kind::MAGIC_LEN)]) == RecordKind::Image {
+                    image_indexes.insert((i - first_image as usize) as u32, images.len() as u32);
+                    images.push(data.to_vec());
+                }
+            }
+        }
+
+        let mut exth = Vec::new();
+        for (id, data) in self.raw_exth(&mut diagnostics) {
+            if id == ExthRecord::KF8BoundaryOffset as u32 {
+                continue;
+            }
+            if id == ExthRecord::CoverOffset as u32 || id == ExthRecord::ThumbOffset as u32 {
+                let index = match data.as_slice() {
+                    [a, b, c, d] => image_indexes.get(&u32::from_be_bytes([*a, *b, *c, *d])),
+                    _ => None,
+                };
+                if let Some(index) = index {
+                    exth.push((id, index.to_be_bytes().to_vec()));
+                }
+                continue;
+            }
+            exth.push((id, data));
+        }
+
+        let header_length = metadata.mobi.header_length as usize;
+        let mobi_header = self
+            .raw_record(0)
+            .and_then(|r| r.data.get(PALMDOC_HEADER_LEN..PALMDOC_HEADER_LEN + header_length))
+            .map(|h| h.to_vec())
+            .unwrap_or_default();
+
+        Ok(Recovered {
+            text,
+            images,
+            diagnostics,
+            pdb_name: metadata.header.name.as_bytes().to_vec(),
+            created: metadata.header.created,
+            modified: metadata.header.modified,
+            mobi_header,
+            exth,
+            name: metadata.mobi.name.clone(),
+            encoding: Some(metadata.text_encoding()),
+        })
+    }
+
+    /// Returns raw content of the record at `index` if it starts within the file,
+    /// reporting whether it had to be truncated
+    fn recover_record(&self, index: usize, diagnostics: &mut Diagnostics) -> Option<&[u8]> {
+        let offset = self.metadata.records.records.get(index)?.0;
+        // Records starting past the end of the file are already reported while loading the book
+        if u64::from(offset) > self.content.len() as u64 {
+            return None;
+        }
+
+        let declared_end = self
+            .metadata
+            .records
+            .records
+            .get(index + 1)
+            .map(|&(offset, _)| u64::from(offset));
+        if let Some(end) = declared_end.filter(|&end| end > self.content.len() as u64) {
+            diagnostics.push(
+                Diagnostic::new(
+                    DiagnosticKind::TruncatedRecord,
+                    format!("record ends at {}, past the end of the file", end),
+                )
+                .record(index)
+                .offset(u64::from(offset)),
+            );
+        }
+        self.raw_record(index).map(|r| r.data)
+    }
+
+    /// Reads EXTH records from the header record, keeping duplicates and stopping
+    /// at the first broken one
+    fn raw_exth(&self, diagnostics: &mut Diagnostics) -> Vec<(u32, Vec<u8>)> {
+        let mut records = Vec::new();
+        if self.metadata.exth.identifier == 0 {
+            return records;
+        }
+        let data = self.raw_record(0).map(|r| r.data).unwrap_or_default();
+        let start = PALMDOC_HEADER_LEN + self.metadata.mobi.header_length as usize;
+        let mut pos = start + 12;
+        for _ in 0..u32_at(data, start + 8).unwrap_or(0) {
+            match (u32_at(data, pos), u32_at(data, pos + 4)) {
+                (Some(id), Some(len)) if len >= 8 && pos + len as usize <= data.len() => {
+                    records.push((id, data[pos + 8..pos + len as usize].to_vec()));
+                    pos += len as usize;
+                }
+                _ => {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            DiagnosticKind::InvalidExthRecord,
+                            "EXTH record exceeds the header record",
+                        )
+                        .header(HeaderKind::Exth)
+                        .record(0),
+                    );
+                    break;
+                }
+            }
+        }
+        records
+    }
+}
+
+impl Recovered {
+    /// Writes out a repaired book, holding recovered text in uncompressed records
+    /// followed by recovered images and FLIS, FCIS and EOF records.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let text_records = self.split_text();
+        let first_image = 1 + text_records.len();
+        let flis = first_image + self.images.len();
+
+        let mut records: Vec<&[u8]> = Vec::with_capacity(flis + 3);
+        let record0 = self.record0(text_records.len(), first_image, flis)?;
+        let (flis_record, fcis_record) = (Flis::default().to_bytes(), Fcis::new(self.text.len() as u32).to_bytes());
+        records.push(&record0);
+        records.extend(text_records);
+        records.extend(self.images.iter().map(|i| i.as_slice()));
+        records.push(&flis_record);
+        records.push(&fcis_record);
+        records.push(EOF_RECORD);
+        let record_count = u16::try_from(records.len()).map_err(|_| too_large("record count", records.len()))?;
+
+        let mut header = vec![0; PDB_HEADER_LEN];
+        let name_len = self
+            .pdb_name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.pdb_name.len())
+            .min(31);
+        header[..name_len].copy_from_slice(&self.pdb_name[..name_len]);
+        put_u32(&mut header, 36, self.created);
+        put_u32(&mut header, 40, self.modified);
+        header[60..68].copy_from_slice(b"BOOKMOBI");
+        put_u32(&mut header, 68, 2 * records.len() as u32 - 1);
+        header[76..78].copy_from_slice(&record_count.to_be_bytes());
+        writer.write_all(&header)?;
+
+        let mut offset = PDB_HEADER_LEN + 8 * records.len() + 2;
+        for (i, record) in records.iter().enumerate() {
+            let record_offset = u32::try_from(offset).map_err(|_| too_large("record offset", offset))?;
+            writer.write_all(&record_offset.to_be_bytes())?;
+            writer.write_all(&(2 * i as u32).to_be_bytes())?;
+            offset += record.len();
+        }
+        writer.write_all(&[0, 0])?;
+        for record in records {
+            writer.write_all(record)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the repaired book, as written by `write`
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Splits text into records, not splitting UTF-8 characters between them
+    fn split_text(&self) -> Vec<&[u8]> {
+        let mut records = Vec::new();
+        let mut text = &self.text[..];
+        while !text.is_empty() {
+            let mut end = text.len().min(RECORD_SIZE);
+            if self.encoding == Some(TextEncoding::UTF8) && end < text.len() {
+                while end > 0 && text[end] & 0xC0 == 0x80 {
+                    end -= 1;
+                }
+                if end == 0 {
+                    end = text.len().min(RECORD_SIZE);
+                }
+            }
+            records.push(&text[..end]);
+            text = &text[end..];
+        }
+        records
+    }
+
+    /// Builds the header record out of the original MOBI header, pointing its
+    /// indexes at records of the repaired book and dropping the rest of them
+    fn record0(&self, text_records: usize, first_image: usize, flis: usize) -> io::Result<Vec<u8>> {
+        let text_length = u32::try_from(self.text.len()).map_err(|_| too_large("text length", self.text.len()))?;
+        let text_records = u16::try_from(text_records).map_err(|_| too_large("text record count", text_records))?;
+        let mut record0 = vec![0; PALMDOC_HEADER_LEN];
+        put_u16(&mut record0, palmdoc::COMPRESSION, 1);
+        put_u32(&mut record0, palmdoc::TEXT_LENGTH, text_length);
+        put_u16(&mut record0, palmdoc::RECORD_COUNT, text_records);
+        put_u16(&mut record0, palmdoc::RECORD_SIZE, RECORD_SIZE as u16);
+
+        let mut header = self.mobi_header.clone();
+        let header_length = header.len();
+        // Indexes of records that aren't recovered
+        let dropped = (mobi::ORTHOGRAPHIC_INDEX..=mobi::EXTRA_INDEX5).step_by(4).chain(vec![
+            mobi::FIRST_HUFF_RECORD,
+            mobi::SRCS_INDEX,
+            mobi::NCX_INDEX,
+            mobi::DATP_INDEX,
+        ]);
+        for offset in dropped {
+            patch(&mut header, offset, NULL_INDEX);
+        }
+        patch(&mut header, mobi::FIRST_NON_BOOK_INDEX, first_image as u32);
+        patch(
+            &mut header,
+            mobi::FIRST_IMAGE_INDEX,
+            if self.images.is_empty() {
+                NULL_INDEX
+            } else {
+                first_image as u32
+            },
+        );
+        patch(&mut header, mobi::HUFF_RECORD_COUNT, 0);
+        patch(&mut header, mobi::FIRST_DATA_RECORD, 0);
+        patch(&mut header, mobi::DATA_RECORD_COUNT, 0);
+        patch(&mut header, mobi::DRM_OFFSET, NULL_INDEX);
+        for offset in (mobi::DRM_COUNT..=mobi::DRM_FLAGS).step_by(4) {
+            patch(&mut header, offset, 0);
+        }
+        // First and last content records, u16 each
+        patch(&mut header, mobi::FIRST_CONTENT_RECORD, (1 << 16) | (flis as u32 - 1));
+        patch(&mut header, mobi::FDST_COUNT, 1);
+        patch(&mut header, mobi::FCIS_RECORD, flis as u32 + 1);
+        patch(&mut header, mobi::FCIS_COUNT, 1);
+        patch(&mut header, mobi::FLIS_RECORD, flis as u32);
+        patch(&mut header, mobi::FLIS_COUNT, 1);
+        patch(&mut header, mobi::SRCS_COUNT, 0);
+        if header_length >= mobi::EXTRA_DATA_FLAGS + 2 {
+            put_u16(&mut header, mobi::EXTRA_DATA_FLAGS, 0);
+        }
+
+        let exth_flags = u32_at(&header, mobi::EXTH_FLAGS).unwrap_or(0);
+        let exth_flags = if self.exth.is_empty() {
+            exth_flags & !EXTH_ON_FLAG
+        } else {
+            exth_flags | EXTH_ON_FLAG
+        };
+        patch(&mut header, mobi::EXTH_FLAGS, exth_flags);
+
+        let mut exth = Vec::new();
+        if !self.exth.is_empty() {
+            let length: usize = 12 + self.exth.iter().map(|(_, data)| data.len() + 8).sum::<usize>();
+            exth.extend_from_slice(b"EXTH");
+            exth.extend_from_slice(&(length as u32).to_be_bytes());
+            exth.extend_from_slice(&(self.exth.len() as u32).to_be_bytes());
+            for (id, data) in &self.exth {
+                exth.extend_from_slice(&id.to_be_bytes());
+                exth.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+                exth.extend_from_slice(data);
+            }
+            exth.resize(exth.len() + (4 - exth.len() % 4) % 4, 0);
+        }
+
+        let name_offset = PALMDOC_HEADER_LEN + header.len() + exth.len();
+        patch(&mut header, mobi::NAME_OFFSET, name_offset as u32);
+        patch(&mut header, mobi::NAME_LENGTH, self.name.len() as u32);
+
+        record0.extend_from_slice(&header);
+        record0.extend_from_slice(&exth);
+        record0.extend_from_slice(self.name.as_bytes());
+        // Name is followed by at least two zero bytes, padding the record to a multiple of four
+        let padding = 2 + (4 - (record0.len() + 2) % 4) % 4;
+        record0.resize(record0.len() + padding, 0);
+        Ok(record0)
+    }
+}
+
+fn too_large(what: &str, value: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} {} is too large for a PDB file", what, value),
+    )
+}
+
+/// Overwrites a field of the MOBI header if the header is long enough to hold it
+fn patch(header: &mut [u8], offset: usize, value: u32) {
+    if offset + 4 <= header.len() {
+        put_u32(header, offset, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{book, ParseOptions};
+
+    fn damaged_book() -> Vec<u8> {
+        let record0 = book::Record0 {
+            record_count: 5,
+            compression: 2,
+            text_length: 14,
+            first_image_index: 4,
+            extra_data_flags: 0b10,
+            exth: vec![
+                (100, b"Author".to_vec()),
+                (201, 1u32.to_be_bytes().to_vec()),
+                (121, 5u32.to_be_bytes().to_vec()),
+            ],
+            ..Default::default()
+        };
+        let records = [
+            record0.build(),
+            b"\x04za\xc5\xbc\x81".to_vec(),
+            b"b\x80\x20\x81".to_vec(),
+            b"\x04\xc3\xb3\xc5\x82\x81".to_vec(),
+            b"FONT....".to_vec(),
+            b"\xff\xd8\xff\xe0 a truncated image".to_vec(),
+            b"\x89PNG\r\n\x1a\n".to_vec(),
+        ];
+        let mut book = book::pdb(&records, 2);
+        book.truncate(book.len() - 18);
+        book
+    }
+
+    #[test]
+    fn recovers_damaged_book() {
+        let book = damaged_book();
+        let m = Mobi::from_slice_with_options(&book, &ParseOptions::new().lenient()).unwrap();
+        let recovered = m.recover().unwrap();
+
+        assert_eq!(recovered.text, "zażbół".as_bytes());
+        assert_eq!(recovered.images, vec![b"\xff\xd8\xff\xe0 a trunc".to_vec()]);
+        let kinds: Vec<_> = recovered.diagnostics.iter().map(|d| (d.kind, d.record)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (DiagnosticKind::RecordOutOfBounds, Some(6)),
+                (DiagnosticKind::InvalidRecordCount, Some(0)),
+                (DiagnosticKind::InvalidBackReference, Some(2)),
+                (DiagnosticKind::TruncatedRecord, Some(5)),
+            ]
+        );
+        assert_eq!(
+            recovered.exth.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![100, 201]
+        );
+    }
+
+    #[test]
+    fn writes_valid_book() {
+        let book = damaged_book();
+        let m = Mobi::from_slice_with_options(&book, &ParseOptions::new().lenient()).unwrap();
+        let recovered = m.recover().unwrap();
+        let repaired = recovered.to_bytes().unwrap();
+
+        let m = Mobi::from_slice(&repaired).unwrap();
+        let report = m.validate();
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert_eq!(m.content_as_string().unwrap(), "zażbół");
+        assert_eq!(m.author(), Some(String::from("Author")));
+        assert_eq!(m.metadata.mobi.name, "Test Book");
+        assert!(m.metadata.kf8.is_none());
+        assert_eq!(m.record_kinds()[2], RecordKind::Image);
+
+        let cover = m.metadata.exth.get_record(ExthRecord::CoverOffset).unwrap();
+        assert_eq!(cover, &0u32.to_be_bytes());
+        assert!(m.recover().unwrap().diagnostics.is_empty());
+    }
+
+    #[test]
+    fn splits_text_at_character_boundaries() {
+        let recovered = Recovered {
+            text: "ż".repeat(RECORD_SIZE).into_bytes(),
+            encoding: Some(TextEncoding::UTF8),
+            ..Default::default()
+        };
+        let records = recovered.split_text();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| std::str::from_utf8(r).is_ok()));
+    }
+
+    #[test]
+    fn refuses_unrepairable_books() {
+        let recovered = Recovered {
+            images: vec![vec![]; usize::from(u16::MAX)],
+            ..Default::default()
+        };
+        let e = recovered.to_bytes().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let record0 = book::Record0 {
+            record_count: 1,
+            header_length: 264,
+            format_version: 8,
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), b"text".to_vec()], 2);
+        assert!(Mobi::from_slice(&book).unwrap().recover().is_err());
+    }
+}
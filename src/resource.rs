@@ -0,0 +1,168 @@
+use crate::{Mobi, RecordKind};
+use std::io;
+
+/// Length of AUDI and VIDE record headers, holding the offset and the size
+/// of media data after their magic
+const MEDIA_HEADER_LEN: usize = 12;
+const UNKNOWN_MIME_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of media held by a resource record
+pub enum ResourceKind {
+    Image,
+    /// Audio clip of an AUDI record, usually MP3
+    Audio,
+    /// Video clip of a VIDE record, usually MP4
+    Video,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Media embedded in a resource record of the book
+pub struct Resource<'a> {
+    /// Index of the PDB record holding the resource
+    pub record: usize,
+    pub kind: ResourceKind,
+    /// MIME type detected from the media, `application/octet-stream` if it's unknown
+    pub mime_type: &'static str,
+    /// Offset of the media from the beginning of the file
+    pub offset: u64,
+    /// Media without the header of its record
+    pub data: &'a [u8],
+}
+
+impl<'a> Resource<'a> {
+    /// Strips the header of AUDI and VIDE records, which holds the offset and
+    /// the size of media data within the record
+    fn parse(record: usize, offset: u32, kind: ResourceKind, data: &'a [u8]) -> io::Result<Resource<'a>> {
+        let (start, data) = match kind {
+            ResourceKind::Image => (0, data),
+            ResourceKind::Audio | ResourceKind::Video => {
+                let header = data.get(..MEDIA_HEADER_LEN).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("record {}: media header exceeds the record", record),
+                    )
+                })?;
+                let u32_at = |pos: usize| {
+                    u32::from_be_bytes([header[pos], header[pos + 1], header[pos + 2], header[pos + 3]]) as usize
+                };
+                let (start, size) = (u32_at(4), u32_at(8));
+                let media = start
+                    .checked_add(size)
+                    .filter(|_| start >= MEDIA_HEADER_LEN)
+                    .and_then(|end| data.get(start..end))
+                    .ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "record {}: media of {} bytes at offset {} is outside of the record",
+                                record, size, start
+                            ),
+                        )
+                    })?;
+                (start, media)
+            }
+        };
+
+        Ok(Resource {
+            record,
+            kind,
+            mime_type: mime_type(kind, data),
+            offset: u64::from(offset) + start as u64,
+            data,
+        })
+    }
+}
+
+fn mime_type(kind: ResourceKind, data: &[u8]) -> &'static str {
+    let is_mp4 = data.get(4..8) == Some(&b"ftyp"[..]);
+    match kind {
+        ResourceKind::Image if data.starts_with(b"\xff\xd8\xff") => "image/jpeg",
+        ResourceKind::Image if data.starts_with(b"GIF8") => "image/gif",
+        ResourceKind::Image if data.starts_with(b"\x89PNG") => "image/png",
+        ResourceKind::Image if data.starts_with(b"BM") => "image/bmp",
+        // MP3 files start with either ID3 tags or frame sync bits
+        ResourceKind::Audio if data.starts_with(b"ID3") => "audio/mpeg",
+        ResourceKind::Audio if data.len() > 1 && data[0] == 0xFF && data[1] & 0xE0 == 0xE0 => "audio/mpeg",
+        ResourceKind::Audio if is_mp4 => "audio/mp4",
+        ResourceKind::Video if is_mp4 => "video/mp4",
+        _ => UNKNOWN_MIME_TYPE,
+    }
+}
+
+impl<'a> Mobi<'a> {
+    /// Returns images, audio and video clips embedded in records of the book,
+    /// in the order of records
+    pub fn resources(&self) -> io::Result<Vec<Resource<'_>>> {
+        let mut resources = Vec::new();
+        for (index, kind) in self.kinds().iter().enumerate() {
+            let kind = match kind {
+                RecordKind::Image => ResourceKind::Image,
+                RecordKind::Audio => ResourceKind::Audio,
+                RecordKind::Video => ResourceKind::Video,
+                _ => continue,
+            };
+            if let Some(record) = self.raw_record(index) {
+                resources.push(Resource::parse(index, record.offset, kind, record.data)?);
+            }
+        }
+        Ok(resources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+
+    #[test]
+    fn parses_resources() {
+        let record0 = book::Record0 {
+            first_image_index: 1,
+            ..Default::default()
+        };
+        let records = [
+            record0.build(),
+            b"GIF89a".to_vec(),
+            b"AUDI\0\0\0\x0c\0\0\0\x04ID3\x04".to_vec(),
+            b"VIDE\0\0\0\x0c\0\0\0\x0c\0\0\0\x18ftypmp42".to_vec(),
+            b"AUDI\0\0\0\x0c\0\0\0\x04\xff\xfb\x90\x64\0\0".to_vec(),
+        ];
+        let book = book::pdb(&records, 2);
+        let m = Mobi::new(&book).unwrap();
+        let resources = m.resources().unwrap();
+
+        let summary: Vec<_> = resources.iter().map(|r| (r.record, r.kind, r.mime_type)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, ResourceKind::Image, "image/gif"),
+                (2, ResourceKind::Audio, "audio/mpeg"),
+                (3, ResourceKind::Video, "video/mp4"),
+                (4, ResourceKind::Audio, "audio/mpeg"),
+            ]
+        );
+        assert_eq!(resources[1].data, b"ID3\x04");
+        let offset = resources[1].offset as usize;
+        assert_eq!(&book[offset..offset + 4], b"ID3\x04");
+    }
+
+    #[test]
+    fn rejects_invalid_media_offsets() {
+        assert!(Resource::parse(1, 0, ResourceKind::Audio, b"AUDI\0\0\0\x20\0\0\0\0").is_err());
+        assert!(Resource::parse(1, 0, ResourceKind::Video, b"VIDE\0\0\0\x08\0\0\0\0").is_err());
+        assert!(Resource::parse(1, 0, ResourceKind::Video, b"VIDE\0\0\0\x0c\0\0\0\x01").is_err());
+        assert!(Resource::parse(1, 0, ResourceKind::Video, b"VIDE\0\0\0\x0c\xff\xff\xff\xff").is_err());
+        assert!(Resource::parse(1, 0, ResourceKind::Video, b"VIDE\0\0\0\x0c").is_err());
+
+        let resource = Resource::parse(1, 100, ResourceKind::Video, b"VIDE\0\0\0\x0c\0\0\0\0").unwrap();
+        assert_eq!(
+            (resource.offset, resource.data, resource.mime_type),
+            (112, &b""[..], UNKNOWN_MIME_TYPE)
+        );
+
+        // Padding after the media is left out
+        let resource = Resource::parse(1, 0, ResourceKind::Audio, b"AUDI\0\0\0\x0c\0\0\0\x02ID\0\0").unwrap();
+        assert_eq!(resource.data, b"ID");
+    }
+}
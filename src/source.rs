@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Random access source of book content. Implementing it lets books be read
+/// from any storage, e.g. remote objects fetched with range requests.
+pub trait Source {
+    /// Reads up to `len` bytes located at `offset` from the beginning of content.
+    /// Fewer bytes are returned only if content ends earlier.
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+
+    /// Returns size of the whole content
+    fn size(&mut self) -> io::Result<u64>;
+}
+
+impl<S: Source + ?Sized> Source for &mut S {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (**self).read_at(offset, len)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        (**self).size()
+    }
+}
+
+impl Source for &[u8] {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let start = (offset.min(self.len() as u64)) as usize;
+        let end = start + len.min(self.len() - start);
+        Ok(self[start..end].to_vec())
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl Source for Vec<u8> {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        (&self[..]).read_at(offset, len)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+}
+
+impl Source for File {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        read_at(self, offset, len)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+/// Source reading content from an object implementing Read and Seek traits
+#[derive(Debug, Default)]
+pub struct SeekSource<R>(pub R);
+
+impl<R: Read + Seek> SeekSource<R> {
+    pub fn new(reader: R) -> SeekSource<R> {
+        SeekSource(reader)
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.0
+    }
+}
+
+impl<R: Read + Seek> Source for SeekSource<R> {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        read_at(&mut self.0, offset, len)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        self.0.seek(SeekFrom::End(0))
+    }
+}
+
+fn read_at(reader: &mut (impl Read + Seek), offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Source over an object implementing only the Read trait, which can be read
+/// only at increasing offsets. Content between them is skipped.
+#[derive(Debug)]
+pub(crate) struct StreamSource<R> {
+    reader: R,
+    position: u64,
+}
+
+impl<R: Read> StreamSource<R> {
+    pub(crate) fn new(reader: R) -> StreamSource<R> {
+        StreamSource { reader, position: 0 }
+    }
+}
+
+impl<R: Read> Source for StreamSource<R> {
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if offset < self.position {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "stream can't be read at an offset that was already passed",
+            ));
+        }
+        self.position += io::copy(&mut self.reader.by_ref().take(offset - self.position), &mut io::sink())?;
+
+        let mut buf = Vec::new();
+        if self.position == offset {
+            self.reader.by_ref().take(len as u64).read_to_end(&mut buf)?;
+            self.position += buf.len() as u64;
+        }
+        Ok(buf)
+    }
+
+    fn size(&mut self) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "size of a stream is not known",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_at_offsets() {
+        let content = b"0123456789".to_vec();
+        let sources: Vec<Box<dyn Source>> = vec![
+            Box::new(&content[..]),
+            Box::new(content.clone()),
+            Box::new(SeekSource::new(Cursor::new(content.clone()))),
+        ];
+        for mut source in sources {
+            assert_eq!(source.read_at(6, 2).unwrap(), b"67");
+            assert_eq!(source.read_at(2, 3).unwrap(), b"234");
+            assert_eq!(source.read_at(8, 4).unwrap(), b"89");
+            assert_eq!(source.read_at(12, 4).unwrap(), b"");
+            assert_eq!(source.size().unwrap(), 10);
+        }
+    }
+
+    #[test]
+    fn reads_stream_forward() {
+        let mut source = StreamSource::new(&b"0123456789"[..]);
+        assert_eq!(source.read_at(2, 3).unwrap(), b"234");
+        assert_eq!(source.read_at(6, 2).unwrap(), b"67");
+        assert!(source.read_at(2, 3).is_err());
+        assert_eq!(source.read_at(9, 4).unwrap(), b"9");
+        assert_eq!(source.read_at(12, 4).unwrap(), b"");
+    }
+}
@@ -0,0 +1,425 @@
+use crate::headers::mobih::offsets as mobi;
+use crate::headers::palmdoch::PALMDOC_HEADER_LEN;
+use crate::headers::{ExthRecord, NULL_INDEX, PDB_HEADER_LEN};
+use crate::reader::{put_u16, put_u32, u16_at, u32_at};
+use crate::{Mobi, RecordKind};
+#[cfg(feature = "deflate")]
+use std::io::Read;
+use std::io::{self, Write};
+#[cfg(feature = "deflate")]
+use zip::{result::ZipError, ZipArchive};
+
+const SRCS_MAGIC: &[u8] = b"SRCS";
+/// Length of the SRCS record header preceding the archive
+const SRCS_HEADER_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A file unpacked from the archive of sources embedded by kindlegen
+pub struct SourceFile {
+    /// Path of the file within the archive
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl<'a> Mobi<'a> {
+    /// Returns the zip archive of sources (OPF, HTML, images...) embedded by
+    /// kindlegen in the SRCS record, or `None` if there is no such record.
+    pub fn source_archive(&self) -> io::Result<Option<&[u8]>> {
+        let index = match self.source_records() {
+            Some((index, _)) => index,
+            None => return Ok(None),
+        };
+        let data = self.raw_record(index).map(|r| r.data).unwrap_or_default();
+        if data.len() < SRCS_HEADER_LEN || !data.starts_with(SRCS_MAGIC) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("record {} isn't a valid SRCS record", index),
+            ));
+        }
+        Ok(Some(&data[SRCS_HEADER_LEN..]))
+    }
+
+    /// Unpacks files of the source archive, skipping directories. Returns no
+    /// files if there is no archive. Only available with feature `deflate`.
+    #[cfg(feature = "deflate")]
+    pub fn source_files(&self) -> io::Result<Vec<SourceFile>> {
+        let archive = match self.source_archive()? {
+            Some(archive) => archive,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut archive = ZipArchive::new(io::Cursor::new(archive)).map_err(invalid_archive)?;
+        let mut files = Vec::new();
+        let mut unpacked = 0;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(invalid_archive)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut data = Vec::with_capacity(self.options.capacity(file.size() as usize));
+            // Reading a byte past the limit is enough to tell it's exceeded
+            let max = self.options.max_decompressed_size.saturating_sub(unpacked);
+            (&mut file)
+                .take(max.saturating_add(1))
+                .read_to_end(&mut data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", file.name(), e)))?;
+            unpacked += data.len() as u64;
+            self.options.check_unpacked_size(unpacked)?;
+            files.push(SourceFile {
+                name: file.name().to_owned(),
+                data,
+            });
+        }
+        Ok(files)
+    }
+
+    /// Writes the book without records holding the source archive, updating
+    /// record indexes of headers which follow them. The book is written unchanged
+    /// if there is no archive.
+    pub fn write_without_sources(&self, mut writer: impl Write) -> io::Result<()> {
+        let (start, count) = match self.source_records() {
+            Some(records) => records,
+            None => return writer.write_all(&self.content),
+        };
+        let removed = start..start + count;
+
+        let mut records: Vec<Vec<u8>> = Vec::with_capacity(self.record_count() - count);
+        for i in (0..self.record_count()).filter(|i| !removed.contains(i)) {
+            records.push(self.raw_record(i).map(|r| r.data.to_vec()).unwrap_or_default());
+        }
+
+        let header_length = self.metadata.mobi.header_length as usize;
+        shift_indexes(&mut records[0], start, count);
+        put_field(&mut records[0], mobi::SRCS_INDEX, header_length, NULL_INDEX);
+        put_field(&mut records[0], mobi::SRCS_COUNT, header_length, 0);
+        // Indexes of KF8 headers are relative to their record, so they only change
+        // if removed records follow it
+        if let Some(kf8) = &self.metadata.kf8 {
+            let record0 = kf8.record0 as usize;
+            if record0 < start {
+                shift_indexes(&mut records[record0], start - record0, count);
+            }
+        }
+
+        // Records get unique ids anew, like ones written by kindlegen, so the
+        // seed of unique ids follows the last of them
+        let mut header = self.content[..PDB_HEADER_LEN].to_vec();
+        put_u32(&mut header, 68, 2 * records.len() as u32 - 1);
+        put_u16(&mut header, 76, records.len() as u16);
+        writer.write_all(&header)?;
+        let mut offset = PDB_HEADER_LEN + 8 * records.len() + 2;
+        for (i, data) in records.iter().enumerate() {
+            writer.write_all(&(offset as u32).to_be_bytes())?;
+            writer.write_all(&(2 * i as u32).to_be_bytes())?;
+            offset += data.len();
+        }
+        writer.write_all(&[0, 0])?;
+        for data in &records {
+            writer.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the index and number of records holding the source archive, linked
+    /// from MOBI header or found by their magic bytes
+    fn source_records(&self) -> Option<(usize, usize)> {
+        let mobi = &self.metadata.mobi;
+        let is_srcs = |index: usize| match self.raw_record(index) {
+            Some(record) => record.data.starts_with(SRCS_MAGIC),
+            None => false,
+        };
+        if mobi.srcs_index != NULL_INDEX && is_srcs(mobi.srcs_index as usize) {
+            let count = (mobi.srcs_count as usize).max(1);
+            return Some((
+                mobi.srcs_index as usize,
+                count.min(self.record_count() - mobi.srcs_index as usize),
+            ));
+        }
+        self.kinds()
+            .iter()
+            .position(|&kind| kind == RecordKind::Srcs)
+            .map(|index| (index, 1))
+    }
+}
+
+/// Offsets of record indexes in MOBI header, relative to its beginning
+const INDEX_OFFSETS: [usize; 18] = [
+    mobi::ORTHOGRAPHIC_INDEX,
+    mobi::INFLECTION_INDEX,
+    mobi::INDEX_NAMES,
+    mobi::INDEX_KEYS,
+    mobi::EXTRA_INDEX0,
+    mobi::EXTRA_INDEX0 + 4,
+    mobi::EXTRA_INDEX0 + 8,
+    mobi::EXTRA_INDEX0 + 12,
+    mobi::EXTRA_INDEX0 + 16,
+    mobi::EXTRA_INDEX5,
+    mobi::FIRST_NON_BOOK_INDEX,
+    mobi::FIRST_IMAGE_INDEX,
+    mobi::FIRST_HUFF_RECORD,
+    mobi::FIRST_DATA_RECORD,
+    mobi::FCIS_RECORD,
+    mobi::FLIS_RECORD,
+    mobi::NCX_INDEX,
+    mobi::DATP_INDEX,
+];
+/// Offsets of record indexes only present in MOBI header of KF8 books
+const KF8_INDEX_OFFSETS: [usize; 4] = [
+    mobi::FDST_INDEX,
+    mobi::FRAGMENT_INDEX,
+    mobi::SKELETON_INDEX,
+    mobi::GUIDE_INDEX,
+];
+
+/// Updates record indexes of headers in `record0` for `count` records removed
+/// at `start`, indexes being relative to `record0`
+fn shift_indexes(record0: &mut [u8], start: usize, count: usize) {
+    let header_length = u32_at(record0, PALMDOC_HEADER_LEN + mobi::HEADER_LENGTH).unwrap_or(0) as usize;
+    let format_version = u32_at(record0, PALMDOC_HEADER_LEN + mobi::FORMAT_VERSION).unwrap_or(0);
+    let shift = |index: u32| shift_index(index, start, count);
+
+    let kf8_offsets: &[usize] = if format_version >= 8 { &KF8_INDEX_OFFSETS } else { &[] };
+    for &offset in INDEX_OFFSETS.iter().chain(kf8_offsets) {
+        if let Some(index) = u32_at(record0, PALMDOC_HEADER_LEN + offset) {
+            put_field(record0, offset, header_length, shift(index));
+        }
+    }
+    // First and last content records of books older than KF8
+    if format_version < 8 && header_length >= mobi::FDST_COUNT && record0.len() >= PALMDOC_HEADER_LEN + mobi::FDST_COUNT
+    {
+        for &offset in &[mobi::FIRST_CONTENT_RECORD, mobi::LAST_CONTENT_RECORD] {
+            let offset = PALMDOC_HEADER_LEN + offset;
+            let index = u16_at(record0, offset).unwrap_or_default();
+            let index = match index {
+                u16::MAX => u16::MAX,
+                index => shift(u32::from(index)) as u16,
+            };
+            put_u16(record0, offset, index);
+        }
+    }
+    shift_exth_boundary(record0, header_length, start, count);
+}
+
+/// Decreases `index` by `count` if it points past records removed at `start`,
+/// and drops it if it points at one of them
+fn shift_index(index: u32, start: usize, count: usize) -> u32 {
+    match index as usize {
+        _ if index == NULL_INDEX => index,
+        i if i >= start + count => index - count as u32,
+        i if i >= start => NULL_INDEX,
+        _ => index,
+    }
+}
+
+/// Updates the KF8 boundary EXTH record for `count` records removed at `start`
+fn shift_exth_boundary(record0: &mut [u8], header_length: usize, start: usize, count: usize) {
+    let exth = PALMDOC_HEADER_LEN + header_length;
+    if record0.get(exth..exth + 4) != Some(b"EXTH") {
+        return;
+    }
+    let mut pos = exth + 12;
+    for _ in 0..u32_at(record0, exth + 8).unwrap_or(0) {
+        let (id, len) = match (u32_at(record0, pos), u32_at(record0, pos + 4)) {
+            (Some(id), Some(len)) if len >= 8 => (id, len as usize),
+            _ => return,
+        };
+        if id == ExthRecord::KF8BoundaryOffset as u32 && len == 12 {
+            if let Some(index) = u32_at(record0, pos + 8) {
+                put_u32(record0, pos + 8, shift_index(index, start, count));
+            }
+        }
+        pos += len;
+    }
+}
+
+/// Overwrites a field of MOBI header at `offset` from its beginning, if the
+/// header of `header_length` is long enough to hold it
+fn put_field(record0: &mut [u8], offset: usize, header_length: usize, value: u32) {
+    let start = PALMDOC_HEADER_LEN + offset;
+    if offset + 4 <= header_length && start + 4 <= record0.len() {
+        put_u32(record0, start, value);
+    }
+}
+
+#[cfg(feature = "deflate")]
+fn invalid_archive(e: ZipError) -> io::Error {
+    match e {
+        ZipError::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, format!("invalid source archive: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book;
+    use crate::trailer::tests::{FCIS_RECORD, FLIS_RECORD};
+    use crate::trailer::{Fcis, Flis, EOF_RECORD};
+    #[cfg(feature = "deflate")]
+    use crate::{LimitExceeded, ParseOptions};
+
+    /// Offsets of the index and count of SRCS records in record 0
+    const SRCS_RECORD: usize = 224;
+    const SRCS_COUNT: usize = 228;
+
+    /// Builds a zip archive out of `(name, compressed, content)` entries, names
+    /// ending with a slash being directories
+    #[cfg(feature = "deflate")]
+    fn zip(files: &[(&str, bool, &[u8])]) -> Vec<u8> {
+        use zip::write::{FileOptions, ZipWriter};
+        use zip::CompressionMethod;
+
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        for &(name, compressed, data) in files {
+            let method = if compressed {
+                CompressionMethod::Deflated
+            } else {
+                CompressionMethod::Stored
+            };
+            let options = FileOptions::default().compression_method(method);
+            if name.ends_with('/') {
+                writer.add_directory(name, options).unwrap();
+            } else {
+                writer.start_file(name, options).unwrap();
+                writer.write_all(data).unwrap();
+            }
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn book_with_sources(archive: &[u8]) -> Vec<u8> {
+        let record0 = book::Record0 {
+            record_count: 1,
+            text_length: 3,
+            first_image_index: 2,
+            fields: vec![(SRCS_RECORD, 3), (SRCS_COUNT, 1), (FLIS_RECORD, 4), (FCIS_RECORD, 5)],
+            ..Default::default()
+        };
+        let mut srcs = b"SRCS\0\0\0\x10".to_vec();
+        srcs.extend_from_slice(&(archive.len() as u32).to_be_bytes());
+        srcs.extend_from_slice(&[0; 4]);
+        srcs.extend_from_slice(archive);
+
+        let records = [
+            record0.build(),
+            b"abc".to_vec(),
+            b"\x89PNG\r\n\x1a\n".to_vec(),
+            srcs,
+            Flis::default().to_bytes(),
+            Fcis::new(3).to_bytes(),
+            EOF_RECORD.to_vec(),
+        ];
+        book::pdb(&records, 2)
+    }
+
+    #[cfg(feature = "deflate")]
+    fn sources() -> Vec<u8> {
+        zip(&[
+            ("book.opf", false, b"<package/>"),
+            ("text/", false, b""),
+            ("text/index.html", true, b"hello hello hello"),
+        ])
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn extracts_sources() {
+        let book = book_with_sources(&sources());
+        let m = Mobi::new(&book).unwrap();
+        assert_eq!(m.source_archive().unwrap(), Some(&sources()[..]));
+
+        let files = m.source_files().unwrap();
+        assert_eq!(
+            files,
+            vec![
+                SourceFile {
+                    name: String::from("book.opf"),
+                    data: b"<package/>".to_vec(),
+                },
+                SourceFile {
+                    name: String::from("text/index.html"),
+                    data: b"hello hello hello".to_vec(),
+                },
+            ]
+        );
+
+        let options = ParseOptions::new().max_decompressed_size(20);
+        let e = Mobi::new_with_options(&book, &options)
+            .unwrap()
+            .source_files()
+            .unwrap_err();
+        assert!(e.get_ref().unwrap().is::<LimitExceeded>());
+
+        // Content no longer matching its checksum
+        let mut corrupted = sources();
+        let pos = corrupted.windows(10).position(|w| w == b"<package/>").unwrap();
+        corrupted[pos] = b'>';
+        let e = Mobi::new(book_with_sources(&corrupted))
+            .unwrap()
+            .source_files()
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let truncated = &sources()[..20];
+        let e = Mobi::new(book_with_sources(truncated))
+            .unwrap()
+            .source_files()
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn strips_sources() {
+        let book = book_with_sources(b"PK\x05\x06");
+        let mut stripped = Vec::new();
+        Mobi::new(&book).unwrap().write_without_sources(&mut stripped).unwrap();
+
+        let m = Mobi::new(&stripped).unwrap();
+        assert_eq!(m.record_count(), 6);
+        assert_eq!(m.source_archive().unwrap(), None);
+        assert_eq!(m.metadata.mobi.srcs_index, NULL_INDEX);
+        assert_eq!(m.metadata.mobi.first_image_index, 2);
+        assert_eq!((m.metadata.mobi.flis_record, m.metadata.mobi.fcis_record), (3, 4));
+        assert_eq!(m.validate().issues, vec![]);
+
+        let mut unchanged = Vec::new();
+        m.write_without_sources(&mut unchanged).unwrap();
+        assert_eq!(unchanged, stripped);
+    }
+
+    #[test]
+    fn drops_indexes_of_removed_records() {
+        let mut record0 = book::Record0 {
+            record_count: 1,
+            text_length: 3,
+            first_image_index: 2,
+            fields: vec![(SRCS_RECORD, 3), (SRCS_COUNT, 2), (FLIS_RECORD, 5), (FCIS_RECORD, 6)],
+            ..Default::default()
+        };
+        // First extra index points at the second SRCS record
+        record0.fields.push((PALMDOC_HEADER_LEN + mobi::EXTRA_INDEX0, 4));
+        let mut srcs = b"SRCS\0\0\0\x10".to_vec();
+        srcs.extend_from_slice(&[0; 8]);
+        let records = [
+            record0.build(),
+            b"abc".to_vec(),
+            b"\x89PNG\r\n\x1a\n".to_vec(),
+            srcs,
+            b"CMET".to_vec(),
+            Flis::default().to_bytes(),
+            Fcis::new(3).to_bytes(),
+            EOF_RECORD.to_vec(),
+        ];
+        let book = book::pdb(&records, 2);
+        let mut stripped = Vec::new();
+        Mobi::new(&book).unwrap().write_without_sources(&mut stripped).unwrap();
+
+        let m = Mobi::new(&stripped).unwrap();
+        assert_eq!(m.record_count(), 6);
+        assert_eq!(m.metadata.mobi.extra_index0, NULL_INDEX);
+        assert_eq!((m.metadata.mobi.flis_record, m.metadata.mobi.fcis_record), (3, 4));
+        assert_eq!(m.metadata.header.unique_id_seed, 11);
+        let ids: Vec<_> = m.metadata.records.records.iter().map(|&(_, id)| id).collect();
+        assert_eq!(ids, vec![0, 2, 4, 6, 8, 10]);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::headers::TextEncoding;
+use crate::record::{DecodeError, Record};
+use crate::DecodeErrors;
+use encoding::{all::WINDOWS_1252, DecoderTrap, Encoding};
+use std::io::{self, BufRead, Read};
+use std::{fmt, mem, str};
+
+/// Stateful decoder of book text, which is fed with content of text records one
+/// after another. Bytes of a character split between two records are carried
+/// over until the rest of the character arrives.
+#[derive(Debug, Clone)]
+pub struct TextDecoder {
+    encoding: TextEncoding,
+    pending: Vec<u8>,
+}
+
+impl TextDecoder {
+    pub fn new(encoding: TextEncoding) -> TextDecoder {
+        TextDecoder {
+            encoding,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Decodes `data` appending it to `out`, returning error on first invalid sequence
+    pub fn decode(&mut self, data: &[u8], out: &mut String) -> Result<(), DecodeError> {
+        self.decode_with(data, out, false)
+    }
+
+    /// Decodes `data` appending it to `out`, replacing invalid sequences
+    pub fn decode_lossy(&mut self, data: &[u8], out: &mut String) {
+        // Lossy decoding never fails
+        let _ = self.decode_with(data, out, true);
+    }
+
+    /// Finishes decoding, returning error if text ends in the middle of a character
+    pub fn finish(&mut self) -> Result<(), DecodeError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.pending.clear();
+        Err(DecodeError::UTF8(String::from(
+            "incomplete character at the end of text",
+        )))
+    }
+
+    /// Finishes decoding, replacing a character cut off at the end of text
+    pub fn finish_lossy(&mut self, out: &mut String) {
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            out.push(std::char::REPLACEMENT_CHARACTER);
+        }
+    }
+
+    fn decode_with(&mut self, data: &[u8], out: &mut String, lossy: bool) -> Result<(), DecodeError> {
+        match self.encoding {
+            TextEncoding::UTF8 => self.decode_utf8(data, out, lossy),
+            TextEncoding::CP1252 => {
+                let trap = if lossy {
+                    DecoderTrap::Replace
+                } else {
+                    DecoderTrap::Strict
+                };
+                WINDOWS_1252.decode_to(data, trap, out).map_err(DecodeError::CP1252)
+            }
+        }
+    }
+
+    fn decode_utf8(&mut self, data: &[u8], out: &mut String, lossy: bool) -> Result<(), DecodeError> {
+        let joined;
+        let mut data = if self.pending.is_empty() {
+            data
+        } else {
+            self.pending.extend_from_slice(data);
+            joined = mem::take(&mut self.pending);
+            &joined[..]
+        };
+
+        loop {
+            match str::from_utf8(data) {
+                Ok(text) => {
+                    out.push_str(text);
+                    return Ok(());
+                }
+                Err(e) => {
+                    let (valid, rest) = data.split_at(e.valid_up_to());
+                    out.push_str(str::from_utf8(valid).unwrap_or_default());
+                    match e.error_len() {
+                        None => {
+                            self.pending.extend_from_slice(rest);
+                            return Ok(());
+                        }
+                        Some(len) if lossy => {
+                            out.push(std::char::REPLACEMENT_CHARACTER);
+                            data = &rest[len..];
+                        }
+                        Some(_) => return Err(DecodeError::UTF8(e.to_string())),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Decodes content of consecutive records as a single text
+pub(crate) fn decode_records(records: &[Record], encoding: TextEncoding) -> Result<String, DecodeError> {
+    let mut text = String::new();
+    let mut decoder = TextDecoder::new(encoding);
+    for record in records {
+        decoder.decode(&record.record_data, &mut text)?;
+    }
+    decoder.finish()?;
+    Ok(text)
+}
+
+/// Decodes content of consecutive records as a single text, replacing invalid sequences
+pub(crate) fn decode_records_lossy(records: &[Record], encoding: TextEncoding) -> String {
+    let mut text = String::new();
+    let mut decoder = TextDecoder::new(encoding);
+    for record in records {
+        decoder.decode_lossy(&record.record_data, &mut text);
+    }
+    decoder.finish_lossy(&mut text);
+    text
+}
+
+/// Decodes content of consecutive records as a single text, failing on invalid
+/// sequences or replacing them as chosen with `errors`
+pub(crate) fn decode_records_with(
+    records: &[Record],
+    encoding: TextEncoding,
+    errors: DecodeErrors,
+) -> Result<String, DecodeError> {
+    match errors {
+        DecodeErrors::Fail => decode_records(records, encoding),
+        DecodeErrors::Replace => Ok(decode_records_lossy(records, encoding)),
+    }
+}
+
+/// Joins content of consecutive records
+pub(crate) fn join_records(records: &[Record]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(records.iter().map(|r| r.record_data.len()).sum());
+    for record in records {
+        content.extend_from_slice(&record.record_data);
+    }
+    content
+}
+
+/// Reader over the text of a book, decompressing text records one at a time as
+/// they are read, so that only a single record is held in memory.
+pub struct TextReader<'a> {
+    indexes: Vec<usize>,
+    next: usize,
+    read_record: Box<dyn FnMut(usize) -> io::Result<Vec<u8>> + 'a>,
+    buf: Vec<u8>,
+    pos: usize,
+    offset: u64,
+}
+
+impl<'a> TextReader<'a> {
+    /// Creates a reader over records at `indexes`, with `read_record` returning
+    /// decompressed content of a record at given index.
+    pub(crate) fn new(
+        indexes: Vec<usize>,
+        read_record: impl FnMut(usize) -> io::Result<Vec<u8>> + 'a,
+    ) -> TextReader<'a> {
+        TextReader {
+            indexes,
+            next: 0,
+            read_record: Box::new(read_record),
+            buf: Vec::new(),
+            pos: 0,
+            offset: 0,
+        }
+    }
+
+    /// Returns the offset in uncompressed text of the next byte to be read
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Turns this reader into one yielding decoded text, returning error on
+    /// invalid sequences
+    pub fn decode(self, encoding: TextEncoding) -> DecodedText<'a> {
+        DecodedText::new(self, encoding, false)
+    }
+
+    /// Turns this reader into one yielding decoded text, replacing invalid sequences
+    pub fn decode_lossy(self, encoding: TextEncoding) -> DecodedText<'a> {
+        DecodedText::new(self, encoding, true)
+    }
+
+    /// Returns index of the record that is currently being read, if any
+    pub fn record_index(&self) -> Option<usize> {
+        if self.pos < self.buf.len() {
+            self.indexes.get(self.next.wrapping_sub(1)).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Debug for TextReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextReader")
+            .field("indexes", &self.indexes)
+            .field("next", &self.next)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+impl BufRead for TextReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos >= self.buf.len() && self.next < self.indexes.len() {
+            self.buf = (self.read_record)(self.indexes[self.next])?;
+            self.pos = 0;
+            self.next += 1;
+        }
+        Ok(&self.buf[self.pos.min(self.buf.len())..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.buf.len().saturating_sub(self.pos));
+        self.pos += amt;
+        self.offset += amt as u64;
+    }
+}
+
+impl Read for TextReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(buf.len());
+            buf[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+/// Decoded text of a book, yielded in chunks of at most a record each or
+/// character by character.
+#[derive(Debug)]
+pub struct DecodedText<'a> {
+    reader: TextReader<'a>,
+    decoder: TextDecoder,
+    lossy: bool,
+    chunk: String,
+    pos: usize,
+    finished: bool,
+    /// Error ending the text, yielded once text decoded before it is
+    error: Option<io::Error>,
+}
+
+impl<'a> DecodedText<'a> {
+    fn new(reader: TextReader<'a>, encoding: TextEncoding, lossy: bool) -> DecodedText<'a> {
+        DecodedText {
+            reader,
+            decoder: TextDecoder::new(encoding),
+            lossy,
+            chunk: String::new(),
+            pos: 0,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> TextReader<'a> {
+        self.reader
+    }
+
+    /// Returns next chunk of decoded text, or `None` at the end of text
+    pub fn next_chunk(&mut self) -> Option<io::Result<&str>> {
+        if let Err(e) = self.fill_chunk()? {
+            return Some(Err(e));
+        }
+        let start = self.pos;
+        self.pos = self.chunk.len();
+        Some(Ok(&self.chunk[start..]))
+    }
+
+    /// Decodes text until there's some left to be yielded. Decoding ends at the
+    /// first error, which is returned once text decoded before it is yielded.
+    fn fill_chunk(&mut self) -> Option<io::Result<()>> {
+        while self.pos >= self.chunk.len() {
+            self.chunk.clear();
+            self.pos = 0;
+            if let Some(e) = self.error.take() {
+                return Some(Err(e));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+            let result = match self.reader.fill_buf() {
+                Ok([]) => {
+                    self.finished = true;
+                    if self.lossy {
+                        self.decoder.finish_lossy(&mut self.chunk);
+                        Ok(0)
+                    } else {
+                        self.decoder.finish().map(|_| 0).map_err(invalid)
+                    }
+                }
+                Ok(data) => {
+                    if self.lossy {
+                        self.decoder.decode_lossy(data, &mut self.chunk);
+                        Ok(data.len())
+                    } else {
+                        self.decoder
+                            .decode(data, &mut self.chunk)
+                            .map(|_| data.len())
+                            .map_err(invalid)
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(n) => self.reader.consume(n),
+                Err(e) => {
+                    self.finished = true;
+                    self.error = Some(e);
+                }
+            }
+        }
+        Some(Ok(()))
+    }
+}
+
+impl Iterator for DecodedText<'_> {
+    type Item = io::Result<char>;
+
+    fn next(&mut self) -> Option<io::Result<char>> {
+        if let Err(e) = self.fill_chunk()? {
+            return Some(Err(e));
+        }
+        let c = self.chunk[self.pos..].chars().next()?;
+        self.pos += c.len_utf8();
+        Some(Ok(c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(records: &[&'static [u8]]) -> TextReader<'static> {
+        let records: Vec<Vec<u8>> = records.iter().map(|r| r.to_vec()).collect();
+        TextReader::new((0..records.len()).collect(), move |i| Ok(records[i].clone()))
+    }
+
+    #[test]
+    fn reads_across_records() {
+        let mut reader = reader(&[b"abc", b"", b"def", b"g"]);
+        let mut buf = [0; 2];
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf, b"ab");
+        assert_eq!(reader.offset(), 2);
+        assert_eq!(reader.record_index(), Some(0));
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "cdefg");
+        assert_eq!(reader.offset(), 7);
+        assert_eq!(reader.record_index(), None);
+    }
+
+    #[test]
+    fn reads_lines() {
+        let reader = reader(&[b"first\nsec", b"ond\nthird"]);
+        let lines: Vec<String> = reader.lines().map(|l| l.unwrap()).collect();
+        assert_eq!(lines, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn decodes_characters_split_between_records() {
+        let records: &[&[u8]] = &[b"za\xc5", b"\xbc\xc3", b"\xb3", b"\xc5\x82\xc4\x87"];
+        let mut decoder = TextDecoder::new(TextEncoding::UTF8);
+        let mut text = String::new();
+        for record in records {
+            decoder.decode(record, &mut text).unwrap();
+        }
+        decoder.finish().unwrap();
+        assert_eq!(text, "zażółć");
+
+        let chunks: Vec<String> = {
+            let mut decoded = reader(records).decode(TextEncoding::UTF8);
+            let mut chunks = Vec::new();
+            while let Some(chunk) = decoded.next_chunk() {
+                chunks.push(chunk.unwrap().to_owned());
+            }
+            chunks
+        };
+        assert_eq!(chunks, vec!["za", "ż", "ó", "łć"]);
+
+        let chars: String = reader(records).decode(TextEncoding::UTF8).map(|c| c.unwrap()).collect();
+        assert_eq!(chars, "zażółć");
+    }
+
+    #[test]
+    fn decodes_invalid_sequences() {
+        let mut decoder = TextDecoder::new(TextEncoding::UTF8);
+        let mut text = String::new();
+        assert!(decoder.decode(b"a\xffb", &mut text).is_err());
+
+        let chars: String = reader(&[b"a\xffb\xc5", b"\x82\xc4"])
+            .decode_lossy(TextEncoding::UTF8)
+            .map(|c| c.unwrap())
+            .collect();
+        assert_eq!(chars, "a\u{FFFD}bł\u{FFFD}");
+
+        let mut decoded = reader(&[b"a\xc5"]).decode(TextEncoding::UTF8);
+        assert_eq!(decoded.next().unwrap().unwrap(), 'a');
+        assert_eq!(decoded.next().unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(decoded.next().is_none());
+
+        let mut text = String::new();
+        let mut decoder = TextDecoder::new(TextEncoding::CP1252);
+        decoder.decode(b"caf\xe9", &mut text).unwrap();
+        assert_eq!(text, "café");
+    }
+
+    #[test]
+    fn ends_strict_decoding_at_first_error() {
+        let items: Vec<_> = reader(&[b"a\xffb", b"c"]).decode(TextEncoding::UTF8).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), &'a');
+        assert_eq!(items[1].as_ref().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let valid: String = reader(&[b"ab\xff"])
+            .decode(TextEncoding::UTF8)
+            .filter_map(Result::ok)
+            .collect();
+        assert_eq!(valid, "ab");
+
+        let mut decoded = reader(&[b"ab\xff", b"c"]).decode(TextEncoding::UTF8);
+        assert_eq!(decoded.next_chunk().unwrap().unwrap(), "ab");
+        assert!(decoded.next_chunk().unwrap().is_err());
+        assert!(decoded.next_chunk().is_none());
+
+        let mut decoded = TextReader::new(vec![0], |_| Err(io::Error::other("broken"))).decode(TextEncoding::UTF8);
+        assert!(decoded.next().unwrap().is_err());
+        assert!(decoded.next().is_none());
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut reader = TextReader::new(vec![0], |_| Err(io::Error::new(io::ErrorKind::InvalidData, "broken")));
+        assert!(reader.read(&mut [0; 4]).is_err());
+    }
+}
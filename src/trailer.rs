@@ -0,0 +1,259 @@
+use crate::headers::NULL_INDEX;
+use crate::reader::{MobiReader, Reader};
+use crate::Mobi;
+use std::io;
+
+/// Content of the EOF record closing a book
+pub const EOF_RECORD: &[u8] = b"\xe9\x8e\x0d\x0a";
+
+const FLIS_MAGIC: &str = "FLIS";
+const FCIS_MAGIC: &str = "FCIS";
+const FLIS_LEN: usize = 36;
+const FCIS_LEN: usize = 44;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Structure that holds content of the FLIS record. Meaning of its fields is
+/// unknown, but Kindle devices expect values written by kindlegen, which
+/// `Flis::default` returns.
+pub struct Flis {
+    pub header_length: u32,
+    pub unknown0: u16,
+    pub unknown1: u16,
+    pub unknown2: u32,
+    pub unknown3: u32,
+    pub unknown4: u16,
+    pub unknown5: u16,
+    pub unknown6: u32,
+    pub unknown7: u32,
+    pub unknown8: u32,
+}
+
+impl Default for Flis {
+    fn default() -> Flis {
+        Flis {
+            header_length: 8,
+            unknown0: 65,
+            unknown1: 0,
+            unknown2: 0,
+            unknown3: NULL_INDEX,
+            unknown4: 1,
+            unknown5: 3,
+            unknown6: 3,
+            unknown7: 1,
+            unknown8: NULL_INDEX,
+        }
+    }
+}
+
+impl Flis {
+    /// Parses content of a FLIS record
+    pub fn parse(data: &[u8]) -> io::Result<Flis> {
+        let mut reader = Reader::new(data);
+        check_magic(&mut reader, FLIS_MAGIC)?;
+        Ok(Flis {
+            header_length: reader.read_u32_be()?,
+            unknown0: reader.read_u16_be()?,
+            unknown1: reader.read_u16_be()?,
+            unknown2: reader.read_u32_be()?,
+            unknown3: reader.read_u32_be()?,
+            unknown4: reader.read_u16_be()?,
+            unknown5: reader.read_u16_be()?,
+            unknown6: reader.read_u32_be()?,
+            unknown7: reader.read_u32_be()?,
+            unknown8: reader.read_u32_be()?,
+        })
+    }
+
+    /// Returns content of a FLIS record holding these values
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FLIS_LEN);
+        bytes.extend_from_slice(FLIS_MAGIC.as_bytes());
+        bytes.extend_from_slice(&self.header_length.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown0.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown1.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown2.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown3.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown4.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown5.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown6.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown7.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown8.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Structure that holds content of the FCIS record. Apart from `text_length`,
+/// which has to match the one of the PalmDOC header, meaning of its fields is
+/// unknown and `Fcis::new` fills them with values written by kindlegen.
+pub struct Fcis {
+    pub header_length: u32,
+    pub unknown0: u32,
+    pub unknown1: u32,
+    pub unknown2: u32,
+    /// Length of uncompressed text of the book
+    pub text_length: u32,
+    pub unknown3: u32,
+    pub unknown4: u32,
+    pub unknown5: u32,
+    pub unknown6: u16,
+    pub unknown7: u16,
+    pub unknown8: u32,
+}
+
+impl Fcis {
+    /// Returns a FCIS record for a book holding `text_length` bytes of text
+    pub fn new(text_length: u32) -> Fcis {
+        Fcis {
+            header_length: 20,
+            unknown0: 16,
+            unknown1: 1,
+            unknown2: 0,
+            text_length,
+            unknown3: 0,
+            unknown4: 32,
+            unknown5: 8,
+            unknown6: 1,
+            unknown7: 1,
+            unknown8: 0,
+        }
+    }
+
+    /// Parses content of a FCIS record
+    pub fn parse(data: &[u8]) -> io::Result<Fcis> {
+        let mut reader = Reader::new(data);
+        check_magic(&mut reader, FCIS_MAGIC)?;
+        Ok(Fcis {
+            header_length: reader.read_u32_be()?,
+            unknown0: reader.read_u32_be()?,
+            unknown1: reader.read_u32_be()?,
+            unknown2: reader.read_u32_be()?,
+            text_length: reader.read_u32_be()?,
+            unknown3: reader.read_u32_be()?,
+            unknown4: reader.read_u32_be()?,
+            unknown5: reader.read_u32_be()?,
+            unknown6: reader.read_u16_be()?,
+            unknown7: reader.read_u16_be()?,
+            unknown8: reader.read_u32_be()?,
+        })
+    }
+
+    /// Returns content of a FCIS record holding these values
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(FCIS_LEN);
+        bytes.extend_from_slice(FCIS_MAGIC.as_bytes());
+        bytes.extend_from_slice(&self.header_length.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown0.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown1.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown2.to_be_bytes());
+        bytes.extend_from_slice(&self.text_length.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown3.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown4.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown5.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown6.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown7.to_be_bytes());
+        bytes.extend_from_slice(&self.unknown8.to_be_bytes());
+        bytes
+    }
+}
+
+fn check_magic(reader: &mut impl MobiReader, magic: &str) -> io::Result<()> {
+    if reader.read_string_header(0, magic.len())? != magic {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("record doesn't start with {} magic", magic),
+        ));
+    }
+    reader.set_position(magic.len() as u64);
+    Ok(())
+}
+
+impl<'a> Mobi<'a> {
+    /// Returns the FLIS record linked from MOBI header, or `None` if there is no link.
+    /// Returns an error if the linked record doesn't exist or isn't a FLIS record.
+    pub fn flis(&self) -> io::Result<Option<Flis>> {
+        match self.linked_record(self.metadata.mobi.flis_record)? {
+            Some(data) => Flis::parse(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the FCIS record linked from MOBI header, or `None` if there is no link.
+    /// Returns an error if the linked record doesn't exist or isn't a FCIS record.
+    pub fn fcis(&self) -> io::Result<Option<Fcis>> {
+        match self.linked_record(self.metadata.mobi.fcis_record)? {
+            Some(data) => Fcis::parse(data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn linked_record(&self, index: u32) -> io::Result<Option<&[u8]>> {
+        if index == NULL_INDEX || index == 0 {
+            return Ok(None);
+        }
+        match self.raw_record(index as usize) {
+            Some(record) => Ok(Some(record.data)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("linked record {} does not exist", index),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::book;
+
+    /// Offsets of indexes of FCIS and FLIS records in record 0
+    pub(crate) const FCIS_RECORD: usize = 200;
+    pub(crate) const FLIS_RECORD: usize = 208;
+
+    #[test]
+    fn writes_kindlegen_records() {
+        assert_eq!(
+            Flis::default().to_bytes(),
+            b"FLIS\0\0\0\x08\0\x41\0\0\0\0\0\0\xff\xff\xff\xff\0\x01\0\x03\0\0\0\x03\0\0\0\x01\xff\xff\xff\xff"
+                .to_vec()
+        );
+        assert_eq!(
+            Fcis::new(0x1234).to_bytes(),
+            b"FCIS\0\0\0\x14\0\0\0\x10\0\0\0\x01\0\0\0\0\0\0\x12\x34\0\0\0\0\0\0\0\x20\0\0\0\x08\0\x01\0\x01\0\0\0\0"
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn parses_records() {
+        assert_eq!(Flis::parse(&Flis::default().to_bytes()).unwrap(), Flis::default());
+        assert_eq!(Fcis::parse(&Fcis::new(7).to_bytes()).unwrap(), Fcis::new(7));
+
+        let e = Fcis::parse(&Flis::default().to_bytes()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let e = Flis::parse(b"FLIS\0\0\0\x08").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reads_linked_records() {
+        let record0 = book::Record0 {
+            fields: vec![(FLIS_RECORD, 1), (FCIS_RECORD, 2)],
+            ..Default::default()
+        };
+        let records = [record0.build(), Flis::default().to_bytes(), Fcis::new(3).to_bytes()];
+        let book = book::pdb(&records, 2);
+        let m = Mobi::new(&book).unwrap();
+        assert_eq!(m.flis().unwrap(), Some(Flis::default()));
+        assert_eq!(m.fcis().unwrap().map(|f| f.text_length), Some(3));
+
+        let record0 = book::Record0 {
+            fields: vec![(FLIS_RECORD, 2), (FCIS_RECORD, 5)],
+            ..Default::default()
+        };
+        let book = book::pdb(&[record0.build(), vec![], Fcis::new(3).to_bytes()], 2);
+        let m = Mobi::new(&book).unwrap();
+        assert!(m.flis().is_err());
+        assert!(m.fcis().is_err());
+    }
+}
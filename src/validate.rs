@@ -0,0 +1,561 @@
+use crate::diagnostics::Diagnostics;
+use crate::headers::{ExtHeader, ExthRecord, MobiHeader, PalmDocHeader, NULL_INDEX, PDB_HEADER_LEN};
+use crate::reader::u32_at;
+use crate::record::Record;
+use crate::trailer::{Fcis, Flis};
+use crate::{kind, Compression, Mobi, RecordKind};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// Severity of an issue found by `Mobi::validate`
+pub enum Severity {
+    /// The book can be read, but doesn't follow the format closely
+    Warning,
+    /// Part of the book can't be read or devices may reject it
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Format rule checked by `Mobi::validate`
+pub enum ValidationRule {
+    /// Record offsets increase
+    RecordOrder,
+    /// Records are located between the record list and the end of the file
+    RecordBounds,
+    /// `text_length` of the PalmDOC header matches the size of decompressed text
+    TextLength,
+    /// `record_count` of the PalmDOC header matches text records of the book
+    RecordCount,
+    /// Text records decompress to at most `record_size` bytes
+    RecordSize,
+    /// Text records can be decompressed
+    Decompression,
+    /// Image indexes of MOBI and EXTH headers point to images
+    ImageIndex,
+    /// Lengths of the EXTH header and its records are consistent
+    ExthLength,
+    /// FLIS record is linked from MOBI header and holds values written by kindlegen
+    Flis,
+    /// FCIS record is linked from MOBI header and holds text length of the PalmDOC header
+    Fcis,
+    /// The book ends with an EOF record
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An issue found by `Mobi::validate`
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub rule: ValidationRule,
+    /// Index of the PDB record the issue was found in
+    pub record: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: ")?,
+            Severity::Error => write!(f, "error: ")?,
+        }
+        if let Some(record) = self.record {
+            write!(f, "record {}: ", record)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Result of checking a book against rules of the format
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns whether no errors were found, warnings aside
+    pub fn is_valid(&self) -> bool {
+        self.max_severity() != Some(Severity::Error)
+    }
+
+    /// Returns the highest severity of found issues, if there are any
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.issues.iter().map(|i| i.severity).max()
+    }
+
+    /// Returns issues of passed severity
+    pub fn with_severity(&self, severity: Severity) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(move |i| i.severity == severity)
+    }
+
+    fn push(&mut self, severity: Severity, rule: ValidationRule, record: Option<usize>, message: String) {
+        self.issues.push(ValidationIssue {
+            severity,
+            rule,
+            record,
+            message,
+        });
+    }
+}
+
+/// Headers of either MOBI or KF8 part of a book, along with the index of the
+/// record holding them
+struct Part<'m> {
+    record0: usize,
+    palmdoc: &'m PalmDocHeader,
+    mobi: &'m MobiHeader,
+    exth: &'m ExtHeader,
+}
+
+impl<'a> Mobi<'a> {
+    /// Checks the book against rules of the format, reporting every issue found.
+    /// All text records are decompressed to do so.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let kinds = self.kinds();
+        self.validate_records(&mut report);
+
+        let metadata = &self.metadata;
+        let mut parts = vec![Part {
+            record0: 0,
+            palmdoc: &metadata.palmdoc,
+            mobi: &metadata.mobi,
+            exth: &metadata.exth,
+        }];
+        if let Some(kf8) = &metadata.kf8 {
+            parts.push(Part {
+                record0: kf8.record0 as usize,
+                palmdoc: &kf8.palmdoc,
+                mobi: &kf8.mobi,
+                exth: &kf8.exth,
+            });
+        }
+
+        for part in &parts {
+            self.validate_text(part, kinds, &mut report);
+            self.validate_images(part, kinds, &mut report);
+            self.validate_exth(part, &mut report);
+            if let Some(index) = validate_link(part, part.mobi.flis_record, RecordKind::Flis, kinds, &mut report) {
+                self.validate_flis(index, &mut report);
+            }
+            if let Some(index) = validate_link(part, part.mobi.fcis_record, RecordKind::Fcis, kinds, &mut report) {
+                self.validate_fcis(part, index, &mut report);
+            }
+        }
+
+        if kinds.last() != Some(&RecordKind::Eof) {
+            report.push(
+                Severity::Warning,
+                ValidationRule::Eof,
+                None,
+                String::from("the book doesn't end with an EOF record"),
+            );
+        }
+        report
+    }
+
+    fn validate_records(&self, report: &mut ValidationReport) {
+        let records = &self.metadata.records.records;
+        let list_end = PDB_HEADER_LEN as u64 + 8 * records.len() as u64 + 2;
+        let len = self.content.len() as u64;
+
+        for (i, &(offset, _)) in records.iter().enumerate() {
+            let offset = u64::from(offset);
+            if offset < list_end || offset > len {
+                report.push(
+                    Severity::Error,
+                    ValidationRule::RecordBounds,
+                    Some(i),
+                    format!("record offset {} is outside of {}..{}", offset, list_end, len),
+                );
+            }
+            if i > 0 && offset < u64::from(records[i - 1].0) {
+                report.push(
+                    Severity::Error,
+                    ValidationRule::RecordOrder,
+                    Some(i),
+                    String::from("record starts before the previous one"),
+                );
+            }
+        }
+    }
+
+    fn validate_text(&self, part: &Part, kinds: &[RecordKind], report: &mut ValidationReport) {
+        let (palmdoc, record_count) = (part.palmdoc, usize::from(part.palmdoc.record_count));
+        let available = kinds.len().saturating_sub(part.record0 + 1);
+        if record_count > available {
+            report.push(
+                Severity::Error,
+                ValidationRule::RecordCount,
+                Some(part.record0),
+                format!(
+                    "record count {} exceeds {} records following headers",
+                    record_count, available
+                ),
+            );
+        }
+        if palmdoc.record_size == 0 {
+            report.push(
+                Severity::Error,
+                ValidationRule::RecordSize,
+                Some(part.record0),
+                String::from("record size is 0"),
+            );
+        } else {
+            let (text_length, record_size) = (palmdoc.text_length as usize, usize::from(palmdoc.record_size));
+            let expected = text_length / record_size + (text_length % record_size != 0) as usize;
+            if expected != record_count {
+                report.push(
+                    Severity::Warning,
+                    ValidationRule::RecordCount,
+                    Some(part.record0),
+                    format!(
+                        "record count {} doesn't match {} records needed for text length {}",
+                        record_count, expected, palmdoc.text_length
+                    ),
+                );
+            }
+        }
+
+        if palmdoc.compression() == Compression::Huff {
+            report.push(
+                Severity::Warning,
+                ValidationRule::Decompression,
+                Some(part.record0),
+                String::from("Huff/CDIC compressed text can't be verified"),
+            );
+            return;
+        }
+
+        // Sizes of records are reported below instead of failing on the limit
+        let options = self.options.clone().strict().max_record_size(usize::MAX);
+        let mut text_length = 0;
+        for i in (part.record0 + 1..=part.record0 + record_count).take(available) {
+            let data = self.raw_record(i).map(|r| r.data).unwrap_or_default();
+            let magic = &data[..data.len().min(kind::MAGIC_LEN)];
+            let kind = RecordKind::from_magic(magic);
+            if kind != RecordKind::Unknown && kind != RecordKind::Image {
+                report.push(
+                    Severity::Error,
+                    ValidationRule::RecordCount,
+                    Some(i),
+                    format!("text record looks like a {:?} record", kind),
+                );
+            }
+
+            let entry = self.metadata.records.records[i];
+            let flags = part.mobi.extra_data_flags;
+            match Record::from_raw(i, entry, data, flags, palmdoc, &options, &mut Diagnostics::new()) {
+                Ok(record) => {
+                    text_length += record.record_data.len();
+                    // Zero record size is reported once above
+                    if palmdoc.record_size > 0 && record.record_data.len() > usize::from(palmdoc.record_size) {
+                        report.push(
+                            Severity::Error,
+                            ValidationRule::RecordSize,
+                            Some(i),
+                            format!(
+                                "text record decompresses to {} bytes, more than record size {}",
+                                record.record_data.len(),
+                                palmdoc.record_size
+                            ),
+                        );
+                    }
+                }
+                Err(e) => report.push(Severity::Error, ValidationRule::Decompression, Some(i), e.to_string()),
+            }
+        }
+
+        if text_length != palmdoc.text_length as usize {
+            report.push(
+                Severity::Warning,
+                ValidationRule::TextLength,
+                Some(part.record0),
+                format!(
+                    "text length {} doesn't match {} bytes of decompressed text",
+                    palmdoc.text_length, text_length
+                ),
+            );
+        }
+    }
+
+    fn validate_images(&self, part: &Part, kinds: &[RecordKind], report: &mut ValidationReport) {
+        let first_image = part.mobi.first_image_index;
+        if first_image == NULL_INDEX {
+            return;
+        }
+
+        let mut check = |what: &str, index: u64| {
+            let absolute = part.record0 as u64 + index;
+            match kinds.get(absolute as usize) {
+                None => report.push(
+                    Severity::Error,
+                    ValidationRule::ImageIndex,
+                    Some(part.record0),
+                    format!("{} {} is out of range", what, absolute),
+                ),
+                Some(kind) if *kind != RecordKind::Image => report.push(
+                    Severity::Warning,
+                    ValidationRule::ImageIndex,
+                    Some(absolute as usize),
+                    format!("{} points to a {:?} record", what, kind),
+                ),
+                Some(_) => (),
+            }
+        };
+
+        check("first image", u64::from(first_image));
+        for &(what, position) in &[
+            ("cover", ExthRecord::CoverOffset as u32),
+            ("thumbnail", ExthRecord::ThumbOffset as u32),
+        ] {
+            if let Some([a, b, c, d]) = part.exth.get_record_position(position).map(|r| r.as_slice()) {
+                let offset = u32::from_be_bytes([*a, *b, *c, *d]);
+                if offset != NULL_INDEX {
+                    check(what, u64::from(first_image) + u64::from(offset));
+                }
+            }
+        }
+    }
+
+    /// Walks EXTH records in the raw content of the header record, as parsed
+    /// EXTH header doesn't keep duplicate records
+    fn validate_exth(&self, part: &Part, report: &mut ValidationReport) {
+        if part.exth.identifier == 0 {
+            return;
+        }
+        let data = self.raw_record(part.record0).map(|r| r.data).unwrap_or_default();
+        let mut error =
+            |message: String| report.push(Severity::Error, ValidationRule::ExthLength, Some(part.record0), message);
+        let start = 16 + part.mobi.header_length as usize;
+        let (length, count) = match (u32_at(data, start + 4), u32_at(data, start + 8)) {
+            (Some(length), Some(count)) => (length as usize, count),
+            _ => return error(String::from("EXTH header exceeds the header record")),
+        };
+        let end = start + length;
+        if end > data.len() {
+            return error(format!("EXTH header length {} exceeds the header record", length));
+        }
+
+        let mut pos = start + 12;
+        for _ in 0..count {
+            match u32_at(data, pos + 4) {
+                Some(len) if len >= 8 && pos + len as usize <= end => pos += len as usize,
+                _ => {
+                    return error(format!(
+                        "EXTH records exceed header length {}, {} of them declared",
+                        length, count
+                    ))
+                }
+            }
+        }
+        // Padding to a multiple of four bytes is counted in by some writers
+        if end - pos >= 4 {
+            report.push(
+                Severity::Warning,
+                ValidationRule::ExthLength,
+                Some(part.record0),
+                format!(
+                    "EXTH header length {} exceeds its records by {} bytes",
+                    length,
+                    end - pos
+                ),
+            );
+        }
+    }
+
+    /// Checks the FLIS record at `index` against the one written by kindlegen
+    fn validate_flis(&self, index: usize, report: &mut ValidationReport) {
+        let data = self.raw_record(index).map(|r| r.data).unwrap_or_default();
+        match Flis::parse(data) {
+            Ok(ref flis) if *flis != Flis::default() => report.push(
+                Severity::Warning,
+                ValidationRule::Flis,
+                Some(index),
+                String::from("FLIS record differs from the one written by kindlegen"),
+            ),
+            Ok(_) => (),
+            Err(e) => report.push(
+                Severity::Error,
+                ValidationRule::Flis,
+                Some(index),
+                format!("invalid FLIS record: {}", e),
+            ),
+        }
+    }
+
+    /// Checks that text length of the FCIS record at `index` matches the PalmDOC header
+    fn validate_fcis(&self, part: &Part, index: usize, report: &mut ValidationReport) {
+        let data = self.raw_record(index).map(|r| r.data).unwrap_or_default();
+        match Fcis::parse(data) {
+            Ok(ref fcis) if fcis.text_length != part.palmdoc.text_length => report.push(
+                Severity::Error,
+                ValidationRule::Fcis,
+                Some(index),
+                format!(
+                    "FCIS text length {} doesn't match text length {} of the PalmDOC header",
+                    fcis.text_length, part.palmdoc.text_length
+                ),
+            ),
+            Ok(_) => (),
+            Err(e) => report.push(
+                Severity::Error,
+                ValidationRule::Fcis,
+                Some(index),
+                format!("invalid FCIS record: {}", e),
+            ),
+        }
+    }
+}
+
+/// Checks that `index` of a record linked from MOBI header points to a record of given
+/// kind, returning the absolute index of the record if it does
+fn validate_link(
+    part: &Part,
+    index: u32,
+    kind: RecordKind,
+    kinds: &[RecordKind],
+    report: &mut ValidationReport,
+) -> Option<usize> {
+    let rule = match kind {
+        RecordKind::Flis => ValidationRule::Flis,
+        _ => ValidationRule::Fcis,
+    };
+    if index == NULL_INDEX || index == 0 {
+        report.push(
+            Severity::Warning,
+            rule,
+            Some(part.record0),
+            format!("MOBI header doesn't link a {:?} record", kind),
+        );
+        return None;
+    }
+
+    let absolute = part.record0 + index as usize;
+    if kinds.get(absolute) != Some(&kind) {
+        report.push(
+            Severity::Error,
+            rule,
+            Some(part.record0),
+            format!("{:?} record {} is missing", kind, absolute),
+        );
+        return None;
+    }
+    Some(absolute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trailer::tests::{FCIS_RECORD, FLIS_RECORD};
+    use crate::{book, EOF_RECORD};
+
+    fn book(record0: book::Record0, text: &[&[u8]]) -> Vec<u8> {
+        let mut records = vec![record0.build()];
+        records.extend(text.iter().map(|t| t.to_vec()));
+        records.push(b"\x89PNG....".to_vec());
+        records.push(Flis::default().to_bytes());
+        records.push(Fcis::new(7).to_bytes());
+        records.push(EOF_RECORD.to_vec());
+        book::pdb(&records, 2)
+    }
+
+    fn valid_record0() -> book::Record0 {
+        book::Record0 {
+            record_count: 2,
+            text_length: 7,
+            record_size: 4,
+            first_image_index: 3,
+            fields: vec![(FLIS_RECORD, 4), (FCIS_RECORD, 5)],
+            exth: vec![(201, 0u32.to_be_bytes().to_vec())],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accepts_valid_book() {
+        let book = book(valid_record0(), &[b"abcd", b"efg"]);
+        let report = Mobi::new(&book).unwrap().validate();
+        assert_eq!(report.issues, vec![]);
+        assert!(report.is_valid());
+        assert_eq!(report.max_severity(), None);
+    }
+
+    #[test]
+    fn reports_issues() {
+        let record0 = book::Record0 {
+            text_length: 10,
+            fields: vec![(FLIS_RECORD, NULL_INDEX), (FCIS_RECORD, 2)],
+            exth: vec![(201, 5u32.to_be_bytes().to_vec())],
+            ..valid_record0()
+        };
+        let mut book = book(record0, &[b"abcde", b"efg"]);
+        book.truncate(book.len() - 4);
+        let report = Mobi::new(&book).unwrap().validate();
+
+        let rules: Vec<_> = report.issues.iter().map(|i| (i.severity, i.rule, i.record)).collect();
+        assert_eq!(
+            rules,
+            vec![
+                (Severity::Warning, ValidationRule::RecordCount, Some(0)),
+                (Severity::Error, ValidationRule::RecordSize, Some(1)),
+                (Severity::Warning, ValidationRule::TextLength, Some(0)),
+                (Severity::Error, ValidationRule::ImageIndex, Some(0)),
+                (Severity::Warning, ValidationRule::Flis, Some(0)),
+                (Severity::Error, ValidationRule::Fcis, Some(0)),
+                (Severity::Warning, ValidationRule::Eof, None),
+            ]
+        );
+        assert!(!report.is_valid());
+        assert_eq!(report.with_severity(Severity::Warning).count(), 4);
+    }
+
+    #[test]
+    fn reports_zero_record_size_once() {
+        let record0 = book::Record0 {
+            record_size: 0,
+            ..valid_record0()
+        };
+        let book = book(record0, &[b"abcd", b"efg"]);
+        let report = Mobi::new(&book).unwrap().validate();
+
+        let rules: Vec<_> = report.issues.iter().map(|i| (i.severity, i.rule, i.record)).collect();
+        assert_eq!(rules, vec![(Severity::Error, ValidationRule::RecordSize, Some(0))]);
+    }
+
+    #[test]
+    fn reports_broken_exth_and_records() {
+        let mut book = book(valid_record0(), &[b"abcd", b"efg"]);
+        // Length of the EXTH record, following MOBI header
+        let exth = book::pdb(&[], 0).len() + 8 * 7 + 2 + 16 + 232;
+        book[exth + 16..exth + 20].copy_from_slice(&30u32.to_be_bytes());
+        // Offset of the last record, moved before the previous one
+        book[78 + 8 * 6..78 + 8 * 6 + 4].copy_from_slice(&10u32.to_be_bytes());
+        let report = Mobi::new(&book).unwrap().validate();
+
+        let rules: Vec<_> = report.issues.iter().map(|i| (i.rule, i.record)).collect();
+        assert!(rules.contains(&(ValidationRule::ExthLength, Some(0))));
+        assert!(rules.contains(&(ValidationRule::RecordBounds, Some(6))));
+        assert!(rules.contains(&(ValidationRule::RecordOrder, Some(6))));
+    }
+
+    #[test]
+    fn reports_invalid_trailer_records() {
+        let mut book = book(valid_record0(), &[b"abcd", b"efg"]);
+        let m = Mobi::new(&book).unwrap();
+        let flis = m.raw_record(4).unwrap().offset as usize;
+        let fcis = m.raw_record(5).unwrap().offset as usize;
+        // Unknown field of FLIS and text length of FCIS
+        book[flis + 9] = 0x42;
+        book[fcis + 23] = 8;
+        let report = Mobi::new(&book).unwrap().validate();
+
+        let rules: Vec<_> = report.issues.iter().map(|i| (i.severity, i.rule, i.record)).collect();
+        assert_eq!(
+            rules,
+            vec![
+                (Severity::Warning, ValidationRule::Flis, Some(4)),
+                (Severity::Error, ValidationRule::Fcis, Some(5)),
+            ]
+        );
+    }
+}